@@ -0,0 +1,80 @@
+//! Cost of basic context operations and future wrappers.
+
+use std::{
+    future::{ready, Future},
+    hint::black_box,
+    pin::pin,
+    task::{Context, Poll},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::task::noop_waker_ref;
+use tyco::{context_newtype, FutureExt, TypedContext};
+
+context_newtype!(TraceId(u64), TRACE_ID_CONTEXT);
+context_newtype!(UserId(u64), USER_ID_CONTEXT);
+context_newtype!(TenantId(u64), TENANT_ID_CONTEXT);
+
+fn poll_ready<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match pin!(fut).poll(&mut cx) {
+        Poll::Ready(x) => x,
+        Poll::Pending => unreachable!(),
+    }
+}
+
+fn current(c: &mut Criterion) {
+    let mut group = c.benchmark_group("current");
+
+    group.bench_function("unset", |b| b.iter(|| black_box(TraceId::current())));
+
+    let _guard = TraceId(1).attach();
+    group.bench_function("set", |b| b.iter(|| black_box(TraceId::current())));
+    group.bench_function("is_set", |b| b.iter(|| black_box(TraceId::is_set())));
+
+    group.finish();
+}
+
+fn attach(c: &mut Criterion) {
+    let mut group = c.benchmark_group("attach");
+
+    group.bench_function("attach_drop", |b| {
+        b.iter(|| drop(black_box(TraceId(1)).attach()))
+    });
+    group.bench_function("attach_ref_drop", |b| {
+        let value = TraceId(1);
+        b.iter(|| drop(unsafe { black_box(&value).attach_ref() }))
+    });
+
+    group.finish();
+}
+
+fn poll(c: &mut Criterion) {
+    let mut group = c.benchmark_group("poll");
+
+    group.bench_function("raw", |b| b.iter(|| poll_ready(black_box(ready(())))));
+    group.bench_function("with", |b| {
+        b.iter(|| poll_ready(black_box(ready(())).with(TraceId(1))))
+    });
+    group.bench_function("with_chain_3", |b| {
+        b.iter(|| {
+            poll_ready(
+                black_box(ready(()))
+                    .with(TraceId(1))
+                    .with(UserId(2))
+                    .with(TenantId(3)),
+            )
+        })
+    });
+    group.bench_function("with_chain_2", |b| {
+        b.iter(|| poll_ready(black_box(ready(())).with(TraceId(1)).with(UserId(2))))
+    });
+    group.bench_function("with2", |b| {
+        b.iter(|| poll_ready(black_box(ready(())).with2(TraceId(1), UserId(2))))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, current, attach, poll);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+//! Check, that `tyco` can be used in `#![no_std]` environment with `alloc`.
+//!
+//! Crate is built for target without `std` (like `thumbv7em-none-eabihf`) in CI.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use core::{cell::RefCell, future::Future};
+
+use tyco::{context, ContextSlot, FutureExt, TlsBackend, TypedContext, WithContext};
+
+/// Storage for single-threaded environment.
+pub struct SingleThreaded<T: 'static>(RefCell<ContextSlot<T>>);
+
+// SAFETY: there is only one thread.
+unsafe impl<T> Sync for SingleThreaded<T> {}
+
+impl<T> SingleThreaded<T> {
+    /// Create empty storage.
+    pub const fn new() -> Self {
+        Self(RefCell::new(ContextSlot::new()))
+    }
+}
+
+impl<T> Default for SingleThreaded<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TlsBackend<T> for SingleThreaded<T> {
+    fn try_with<R>(&'static self, f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R) -> Option<R> {
+        Some(f(&self.0))
+    }
+}
+
+/// Identifier of the request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+context!(RequestId, static REQUEST_ID: SingleThreaded<RequestId> = SingleThreaded::new());
+
+/// Get identifier of the current request.
+pub fn current_request_id() -> Option<RequestId> {
+    RequestId::current()
+}
+
+/// Call function with identifier of the request set.
+pub fn with_request_id<R>(request_id: RequestId, f: impl FnOnce() -> R) -> R {
+    request_id.scoped(f)
+}
+
+/// Set identifier of the request for a future.
+pub fn handle_request<F: Future>(request_id: RequestId, fut: F) -> WithContext<F, RequestId> {
+    fut.with(request_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::{current_request_id, handle_request, with_request_id, RequestId};
+
+    #[test]
+    fn custom_backend() {
+        assert_eq!(current_request_id(), None);
+        let inner = with_request_id(RequestId("sync".into()), current_request_id);
+        assert_eq!(inner, Some(RequestId("sync".into())));
+
+        let fut = pin!(handle_request(RequestId("async".into()), async {
+            current_request_id()
+        }));
+        let res = fut.poll(&mut Context::from_waker(Waker::noop()));
+        assert_eq!(res, Poll::Ready(Some(RequestId("async".into()))));
+        assert_eq!(current_request_id(), None);
+    }
+}
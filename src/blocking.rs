@@ -0,0 +1,106 @@
+//! Context propagation into [`tokio::task::spawn_blocking`].
+
+use tokio::task::{self, JoinHandle};
+
+use crate::{ThreadContextPropagator, TypedContext};
+
+/// Builder, capturing current values of several contexts and attaching them in blocking task.
+///
+/// Requires `tokio` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context, ContextBlockingBuilder, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct RequestId(u64);
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct UserId(u64);
+///
+/// context!(RequestId, REQUEST_ID_CONTEXT);
+/// context!(UserId, USER_ID_CONTEXT);
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let _request_guard = RequestId(1).attach();
+///
+/// let handle = ContextBlockingBuilder::new()
+///     .capture::<RequestId>()
+///     .capture::<UserId>()
+///     .spawn(|| (RequestId::current(), UserId::current()));
+///
+/// assert_eq!(handle.await.unwrap(), (Some(RequestId(1)), None));
+/// # });
+/// ```
+#[derive(Default)]
+pub struct ContextBlockingBuilder {
+    inner: ThreadContextPropagator,
+}
+
+impl ContextBlockingBuilder {
+    /// Create builder without any captured contexts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capture current value of the context.
+    ///
+    /// If no value is set, context is left untouched in blocking task.
+    pub fn capture<T: TypedContext + Send>(self) -> Self {
+        Self {
+            inner: self.inner.capture::<T>(),
+        }
+    }
+
+    /// Run function on blocking thread pool with captured contexts attached.
+    ///
+    /// Must be called from the context of tokio runtime.
+    pub fn spawn<F, R>(self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        task::spawn_blocking(self.inner.bind(f))
+    }
+}
+
+/// Run function on blocking thread pool with current value of the context attached.
+///
+/// Basically it is `ContextBlockingBuilder::new().capture::<T>().spawn(f)`.
+///
+/// Requires `tokio` feature.
+pub fn spawn_blocking_with_context<T, F, R>(f: F) -> JoinHandle<R>
+where
+    T: TypedContext + Send,
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    ContextBlockingBuilder::new().capture::<T>().spawn(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spawn_blocking_with_context;
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RequestId(u64);
+
+    crate::context!(RequestId);
+
+    #[tokio::test]
+    async fn spawn_blocking() {
+        let guard = RequestId(1).attach();
+        let handle = spawn_blocking_with_context::<RequestId, _, _>(RequestId::current);
+        drop(guard);
+
+        assert_eq!(handle.await.unwrap(), Some(RequestId(1)));
+    }
+
+    #[tokio::test]
+    async fn spawn_blocking_without_value() {
+        let handle = spawn_blocking_with_context::<RequestId, _, _>(RequestId::current);
+        assert_eq!(handle.await.unwrap(), None);
+    }
+}
@@ -0,0 +1,95 @@
+//! Context types, provided by the crate.
+//!
+//! Requires `std` feature.
+
+use std::{fmt, ops::Deref, panic::Location};
+
+use crate::{ContextGuard, TypedContext};
+
+/// Context, holding location in source code, where execution was entered (like handler of the
+/// request), which helps to find out where the call came from in deeply nested code.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{builtins::CallerContext, TypedContext};
+///
+/// fn nested() -> u32 {
+///     CallerContext::current().unwrap().line()
+/// }
+///
+/// let _guard = CallerContext::attach_here();
+/// assert_eq!(nested(), line!() - 1);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CallerContext(pub &'static Location<'static>);
+
+crate::context!(CallerContext, CALLER_CONTEXT);
+
+impl CallerContext {
+    /// Create context with location of the caller.
+    #[track_caller]
+    pub fn here() -> Self {
+        Self(Location::caller())
+    }
+
+    /// Set location of the caller as current context.
+    #[track_caller]
+    pub fn attach_here() -> ContextGuard<Self> {
+        Self::here().attach()
+    }
+
+    /// Get the location.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.0
+    }
+}
+
+impl Deref for CallerContext {
+    type Target = Location<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl From<&'static Location<'static>> for CallerContext {
+    fn from(value: &'static Location<'static>) -> Self {
+        Self(value)
+    }
+}
+
+impl fmt::Display for CallerContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallerContext;
+    use crate::{FutureExt, TypedContext};
+
+    #[track_caller]
+    fn entry_point() -> crate::ContextGuard<CallerContext> {
+        CallerContext::attach_here()
+    }
+
+    #[tokio::test]
+    async fn caller_location() {
+        assert_eq!(CallerContext::current(), None);
+
+        let (_guard, line) = (entry_point(), line!());
+        let caller = CallerContext::current().unwrap();
+        assert_eq!((caller.file(), caller.line()), (file!(), line));
+
+        let res = tokio::spawn(async { CallerContext::current() }.with_current::<CallerContext>())
+            .await
+            .unwrap();
+        assert_eq!(res, Some(caller));
+        assert_eq!(
+            caller.to_string(),
+            format!("{}:{}:{}", file!(), line, caller.column())
+        );
+    }
+}
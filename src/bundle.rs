@@ -0,0 +1,381 @@
+//! Groups of contexts, captured and attached together.
+
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+
+/// Group of contexts, generated with [`context_bundle!`](crate::context_bundle).
+pub trait ContextBundle: Clone {
+    /// Guards of all contexts of the bundle.
+    type Guards;
+
+    /// Capture current values of all contexts.
+    fn capture_all() -> Self;
+
+    /// Attach all values of the bundle. Missing values are not attached (and not cleared).
+    fn attach_all(&self) -> BundleGuard<Self::Guards>;
+
+    /// Attach all values of the bundle for the duration of the closure call, without cloning them.
+    ///
+    /// # Safety
+    ///
+    /// References to the values are stored in TLS, so same requirements as for
+    /// [`TypedContext::attach_ref`](crate::TypedContext::attach_ref) apply: `f` must not leak guards of
+    /// these contexts or leave them alive after it returns, other than [`ContextGuard`](crate::ContextGuard)s.
+    unsafe fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R;
+}
+
+/// Guard, created with [`ContextBundle::attach_all`], keeping values as current contexts.
+///
+/// Contains guards of all contexts, which restore previous values on drop in reverse order of
+/// attaching.
+#[must_use = "BundleGuard must be held in a binding; dropping it immediately unsets the contexts"]
+pub struct BundleGuard<G> {
+    guards: Option<G>,
+    drop_guards: fn(G),
+}
+
+impl<G> BundleGuard<G> {
+    #[doc(hidden)]
+    pub fn new(guards: G, drop_guards: fn(G)) -> Self {
+        Self {
+            guards: Some(guards),
+            drop_guards,
+        }
+    }
+
+    /// Get guards of individual contexts.
+    ///
+    /// Guards are dropped in declaration order of the tuple, unless dropped explicitly.
+    pub fn into_inner(mut self) -> G {
+        self.guards.take().expect("guards are taken only once")
+    }
+}
+
+impl<G> Drop for BundleGuard<G> {
+    fn drop(&mut self) {
+        if let Some(guards) = self.guards.take() {
+            (self.drop_guards)(guards)
+        }
+    }
+}
+
+impl<G: fmt::Debug> fmt::Debug for BundleGuard<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BundleGuard").field(&self.guards).finish()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, attaching all contexts of a bundle while future is polled.
+    #[derive(Clone, Debug)]
+    pub struct WithContextBundle<F, B> {
+        #[pin]
+        inner: F,
+        bundle: B,
+    }
+}
+
+impl<F, B> WithContextBundle<F, B> {
+    pub(crate) fn new(inner: F, bundle: B) -> Self {
+        Self { inner, bundle }
+    }
+}
+
+impl<F: Future, B: ContextBundle> Future for WithContextBundle<F, B> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let inner = this.inner;
+
+        // Guards are dropped before poll returns.
+        unsafe { this.bundle.in_scope(|| inner.poll(cx)) }
+    }
+}
+
+/// Macro for defining group of contexts, which are captured and attached together.
+///
+/// Generates struct with optional field for each listed context type (which must already implement
+/// [`TypedContext`](crate::TypedContext)) and implements [`ContextBundle`] for it. Bundle can be
+/// attached to a future with [`FutureExt::with_bundle`](crate::FutureExt::with_bundle), which is
+/// cheaper than chaining [`FutureExt::with_current`](crate::FutureExt::with_current) for each type.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context, context_bundle, ContextBundle, FutureExt, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct TraceId(String);
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct UserId(u64);
+///
+/// context!(TraceId, TRACE_ID_CONTEXT);
+/// context!(UserId, USER_ID_CONTEXT);
+///
+/// context_bundle! {
+///     #[derive(Debug)]
+///     pub struct RequestContext {
+///         pub trace_id: TraceId,
+///         pub user_id: UserId,
+///     }
+/// }
+///
+/// let _guard = UserId(1).attach();
+/// let bundle = RequestContext::capture_all();
+/// assert_eq!(bundle.user_id, Some(UserId(1)));
+///
+/// let fut = async { UserId::current() }.with_bundle(bundle);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context_bundle {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Default)]
+        $vis struct $name {
+            $($field_vis $field: Option<$ty>,)*
+        }
+
+        impl $crate::ContextBundle for $name {
+            type Guards = ($(Option<$crate::ContextGuard<$ty>>,)*);
+
+            fn capture_all() -> Self {
+                Self {
+                    $($field: <$ty as $crate::TypedContext>::current(),)*
+                }
+            }
+
+            fn attach_all(&self) -> $crate::BundleGuard<Self::Guards> {
+                #[allow(unused_variables)]
+                fn drop_guards(($($field,)*): <$name as $crate::ContextBundle>::Guards) {
+                    // Variables are dropped in reverse order of declaration.
+                    $(let $field = $field;)*
+                }
+
+                $crate::BundleGuard::new(
+                    ($(self.$field.clone().map($crate::TypedContext::attach),)*),
+                    drop_guards,
+                )
+            }
+
+            #[allow(unsafe_code, unused_variables)]
+            unsafe fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+                // References are valid until guards are dropped at the end of the function, in
+                // reverse order of declaration.
+                $(let $field = self.$field.as_ref().map(|x| unsafe { $crate::TypedContext::attach_ref(x) });)*
+                f()
+            }
+        }
+    };
+}
+
+/// Macro for declaring group of related contexts together with their types.
+///
+/// Implements [`TypedContext`](crate::TypedContext) for each listed type (as [`context!`](crate::context)
+/// does, but without naming TLS variables, so all types can be declared in the same module) and
+/// generates [`context_bundle!`](crate::context_bundle) struct with a field for each of them. Types
+/// should be declared separately and implement [`Clone`].
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context_group, ContextBundle, FutureExt, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct TraceId(String);
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct UserId(u64);
+///
+/// context_group! {
+///     #[derive(Debug)]
+///     pub struct RequestContext {
+///         pub trace_id: TraceId,
+///         pub user_id: UserId,
+///     }
+/// }
+///
+/// let _guard = UserId(1).attach();
+/// let group = RequestContext::capture_all();
+/// assert_eq!(group.user_id, Some(UserId(1)));
+///
+/// let fut = async { UserId::current() }.with_bundle(group);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context_group {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($field_vis:vis $field:ident: $ty:path),* $(,)?
+        }
+    ) => {
+        $(
+            const _: () = {
+                $crate::context!($ty);
+            };
+        )*
+
+        $crate::context_bundle! {
+            $(#[$meta])*
+            $vis struct $name {
+                $($field_vis $field: $ty,)*
+            }
+        }
+    };
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::ContextBundle;
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tenant(u32);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Locale(&'static str);
+
+    crate::context!(Tenant, TENANT_CONTEXT);
+    crate::context!(Locale, LOCALE_CONTEXT);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TraceId(u64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct UserId(u64);
+
+    crate::context_group! {
+        #[derive(Debug, PartialEq)]
+        struct Group {
+            trace_id: TraceId,
+            user_id: UserId,
+        }
+    }
+
+    crate::context_bundle! {
+        #[derive(Debug, PartialEq)]
+        struct Request {
+            tenant: Tenant,
+            locale: Locale,
+        }
+    }
+
+    #[test]
+    fn capture_and_attach() {
+        let bundle = {
+            let _tenant = Tenant(1).attach();
+            let _locale = Locale("en").attach();
+            Request::capture_all()
+        };
+        assert_eq!(Tenant::current(), None);
+
+        let guard = bundle.attach_all();
+        assert_eq!(Tenant::current(), Some(Tenant(1)));
+        assert_eq!(Locale::current(), Some(Locale("en")));
+
+        drop(guard);
+        assert_eq!(Locale::current(), None);
+    }
+
+    #[tokio::test]
+    async fn with_bundle() {
+        let bundle = Request {
+            tenant: Some(Tenant(2)),
+            locale: None,
+        };
+
+        let res = tokio::spawn(
+            async {
+                tokio::task::yield_now().await;
+                (Tenant::current(), Locale::current())
+            }
+            .with_bundle(bundle),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res, (Some(Tenant(2)), None));
+        assert_eq!(Tenant::current(), None);
+    }
+
+    #[test]
+    fn restore_in_reverse_order() {
+        // Both values are attached over the same previous value, so restoring them in order of
+        // attaching would leave the one, attached first, as current value.
+        crate::context_bundle! {
+            struct Twice {
+                first: Tenant,
+                second: Tenant,
+            }
+        }
+
+        let bundle = Twice {
+            first: Some(Tenant(1)),
+            second: Some(Tenant(2)),
+        };
+
+        let guard = bundle.attach_all();
+        assert_eq!(Tenant::current(), Some(Tenant(2)));
+        drop(guard);
+        assert_eq!(Tenant::current(), None);
+
+        unsafe { bundle.in_scope(|| assert_eq!(Tenant::current(), Some(Tenant(2)))) };
+        assert_eq!(Tenant::current(), None);
+    }
+
+    #[test]
+    fn group() {
+        let group = {
+            let _trace = TraceId(1).attach();
+            Group::capture_all()
+        };
+        assert_eq!(
+            group,
+            Group {
+                trace_id: Some(TraceId(1)),
+                user_id: None,
+            }
+        );
+
+        let _user = UserId(2).attach();
+        unsafe {
+            group.in_scope(|| {
+                assert_eq!(TraceId::current(), Some(TraceId(1)));
+                assert_eq!(UserId::current(), Some(UserId(2)));
+            })
+        };
+        assert_eq!(TraceId::current(), None);
+    }
+
+    #[test]
+    fn guard_escaping_scope() {
+        let _tenant = Tenant(1).attach();
+        let guard = {
+            let bundle = Request {
+                tenant: Some(Tenant(2)),
+                locale: None,
+            };
+            unsafe { bundle.in_scope(|| Tenant(3).attach()) }
+        };
+        assert_eq!(Tenant::current(), Some(Tenant(1)));
+
+        drop(guard);
+        assert_eq!(Tenant::current(), Some(Tenant(2)));
+    }
+}
@@ -0,0 +1,160 @@
+//! Contexts, stored per object rather than per thread.
+
+use alloc::sync::Arc;
+use core::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+
+use crate::TypedContext;
+
+/// Cell, holding value of the context, associated with some object (like connection), which can be
+/// attached to futures, working with that object.
+///
+/// Value is read on each poll, so changes with [`ContextCell::set`] are visible starting from the next
+/// poll of futures, created with [`ContextCell::scope`].
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context, ContextCell, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(u64);
+///
+/// context!(TraceId);
+///
+/// struct Connection {
+///     trace_id: ContextCell<TraceId>,
+/// }
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let conn = Connection { trace_id: ContextCell::new() };
+/// conn.trace_id.set(TraceId(1));
+///
+/// let trace_id = conn.trace_id.scope(async { TraceId::current() }).await;
+/// assert_eq!(trace_id, Some(TraceId(1)));
+/// # });
+/// # }
+/// ```
+pub struct ContextCell<T> {
+    value: RefCell<Option<Arc<T>>>,
+}
+
+impl<T: TypedContext> ContextCell<T> {
+    /// Create empty cell.
+    pub const fn new() -> Self {
+        Self {
+            value: RefCell::new(None),
+        }
+    }
+
+    /// Set value of the cell.
+    pub fn set(&self, value: T) {
+        self.value.replace(Some(Arc::new(value)));
+    }
+
+    /// Get clone of the value of the cell.
+    pub fn get(&self) -> Option<T> {
+        self.value.borrow().as_deref().cloned()
+    }
+
+    /// Clear the cell and return its value.
+    pub fn take(&self) -> Option<T> {
+        self.value.take().map(Arc::unwrap_or_clone)
+    }
+
+    /// Set value of the cell as context for a future, while it is polled.
+    ///
+    /// If cell is empty, context is left untouched.
+    pub fn scope<F: Future>(&self, f: F) -> WithContextCell<'_, F, T> {
+        WithContextCell {
+            inner: f,
+            cell: self,
+        }
+    }
+}
+
+impl<T: TypedContext> Default for ContextCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ContextCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContextCell")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, attaching value of [`ContextCell`] while future is polled.
+    pub struct WithContextCell<'a, F, T> {
+        #[pin]
+        inner: F,
+        cell: &'a ContextCell<T>,
+    }
+}
+
+impl<F: Future, T: TypedContext> Future for WithContextCell<'_, F, T> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        // Cell is not borrowed during poll, so future can update it.
+        let value = this.cell.value.borrow().clone();
+        let _guard = value.as_deref().map(|x| unsafe { x.attach_ref() });
+        this.inner.poll(cx)
+    }
+}
+
+/// Macro for defining named [`ContextCell`] for a context type.
+///
+/// `context_cell!(pub TraceIdCell(TraceId))` defines type alias `TraceIdCell` for
+/// `ContextCell<TraceId>`.
+#[macro_export]
+macro_rules! context_cell {
+    ($(#[$meta:meta])* $vis:vis $name:ident($ty:ty)) => {
+        $(#[$meta])*
+        $vis type $name = $crate::ContextCell<$ty>;
+    };
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TraceId(u64);
+
+    crate::context!(TraceId);
+    crate::context_cell!(TraceIdCell(TraceId));
+
+    #[tokio::test]
+    async fn scope() {
+        let cell = TraceIdCell::new();
+        assert_eq!(cell.scope(async { TraceId::current() }).await, None);
+
+        cell.set(TraceId(1));
+        let fut = cell.scope(async {
+            tokio::task::yield_now().await;
+            cell.set(TraceId(2));
+            let before = TraceId::current();
+            tokio::task::yield_now().await;
+            (before, TraceId::current())
+        });
+
+        assert_eq!(fut.await, (Some(TraceId(1)), Some(TraceId(2))));
+        assert_eq!(cell.take(), Some(TraceId(2)));
+        assert_eq!(cell.get(), None);
+    }
+}
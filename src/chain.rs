@@ -0,0 +1,121 @@
+//! Fallback between contexts, representing the same concept.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+
+use crate::TypedContext;
+
+/// Get value of context `A`, falling back to context `B`, converted to common type.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{chain_context, context, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct RequestId(String);
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(String);
+///
+/// context!(RequestId, REQUEST_ID_CONTEXT);
+/// context!(TraceId, TRACE_ID_CONTEXT);
+///
+/// let _guard = TraceId("trace".into()).attach();
+///
+/// let id = chain_context(|x: RequestId| x.0, |x: TraceId| x.0);
+/// assert_eq!(id.as_deref(), Some("trace"));
+/// # }
+/// ```
+pub fn chain_context<A, B, C>(map_a: impl FnOnce(A) -> C, map_b: impl FnOnce(B) -> C) -> Option<C>
+where
+    A: TypedContext,
+    B: TypedContext,
+{
+    A::current_map(map_a).or_else(|| B::current_map(map_b))
+}
+
+pin_project! {
+    /// Wrapper for a future, attaching value of context `A` or, if it is missing, value of context `B`.
+    #[derive(Clone, Debug)]
+    pub struct OrContext<F, A, B> {
+        #[pin]
+        inner: F,
+        value: Option<Result<A, B>>,
+    }
+}
+
+impl<F, A: TypedContext, B: TypedContext> OrContext<F, A, B> {
+    pub(crate) fn new(inner: F) -> Self {
+        let value = A::current().map(Ok).or_else(|| B::current().map(Err));
+
+        Self { inner, value }
+    }
+}
+
+impl<F: Future, A: TypedContext, B: TypedContext> Future for OrContext<F, A, B> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        match this.value {
+            Some(Ok(x)) => {
+                let _guard = unsafe { x.attach_ref() };
+                this.inner.poll(cx)
+            }
+            Some(Err(x)) => {
+                let _guard = unsafe { x.attach_ref() };
+                this.inner.poll(cx)
+            }
+            None => this.inner.poll(cx),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::chain_context;
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RequestId(u64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TraceId(u64);
+
+    crate::context!(RequestId, REQUEST_ID_CONTEXT);
+    crate::context!(TraceId, TRACE_ID_CONTEXT);
+
+    #[test]
+    fn chain() {
+        let id = || chain_context(|x: RequestId| x.0, |x: TraceId| x.0);
+        assert_eq!(id(), None);
+
+        let _trace_guard = TraceId(2).attach();
+        assert_eq!(id(), Some(2));
+
+        let _request_guard = RequestId(1).attach();
+        assert_eq!(id(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn with_current_or() {
+        let current = || async { (RequestId::current(), TraceId::current()) };
+
+        let trace_guard = TraceId(2).attach();
+        let fut = current().with_current_or::<RequestId, TraceId>();
+        let request_guard = RequestId(1).attach();
+        let preferred = current().with_current_or::<RequestId, TraceId>();
+        drop((request_guard, trace_guard));
+
+        assert_eq!(fut.await, (None, Some(TraceId(2))));
+        assert_eq!(preferred.await, (Some(RequestId(1)), None));
+    }
+}
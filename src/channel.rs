@@ -0,0 +1,145 @@
+//! [`tokio::sync::mpsc`] channels, passing context alongside with messages.
+
+use tokio::sync::mpsc::{self, error::SendError};
+
+use crate::{ContextGuard, TypedContext};
+
+/// Create bounded channel, which passes current value of context `C` with each message.
+///
+/// Requires `tokio` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context, FutureExt, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct RequestId(u64);
+///
+/// context!(RequestId);
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let (tx, mut rx) = tyco::channel::<RequestId, &str>(8);
+///
+/// tokio::spawn(async move { tx.send("ping").await.unwrap() }.with(RequestId(1)));
+///
+/// let (guard, msg) = rx.recv().await.unwrap();
+/// assert_eq!(msg, "ping");
+/// assert_eq!(RequestId::current(), Some(RequestId(1)));
+/// drop(guard);
+/// # });
+/// ```
+pub fn channel<C: TypedContext, M>(buffer: usize) -> (ContextSender<C, M>, ContextReceiver<C, M>) {
+    let (tx, rx) = mpsc::channel(buffer);
+    (ContextSender { inner: tx }, ContextReceiver { inner: rx })
+}
+
+/// Sending half of the channel, created with [`channel`].
+///
+/// Requires `tokio` feature.
+#[derive(Debug)]
+pub struct ContextSender<C, M> {
+    inner: mpsc::Sender<(Option<C>, M)>,
+}
+
+impl<C: TypedContext, M> ContextSender<C, M> {
+    /// Send message with current value of the context.
+    ///
+    /// If channel is closed, message is returned in error (context is dropped).
+    pub async fn send(&self, msg: M) -> Result<(), SendError<M>> {
+        self.send_with(C::current(), msg).await
+    }
+
+    /// Send message with explicitly specified value of the context.
+    ///
+    /// Useful when message already carries context or when it must be sent without context.
+    pub async fn send_with(&self, context: Option<C>, msg: M) -> Result<(), SendError<M>> {
+        self.inner
+            .send((context, msg))
+            .await
+            .map_err(|SendError((_, msg))| SendError(msg))
+    }
+
+    /// Get underlying sender.
+    pub fn into_inner(self) -> mpsc::Sender<(Option<C>, M)> {
+        self.inner
+    }
+}
+
+impl<C, M> Clone for ContextSender<C, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Receiving half of the channel, created with [`channel`].
+///
+/// Requires `tokio` feature.
+#[derive(Debug)]
+pub struct ContextReceiver<C, M> {
+    inner: mpsc::Receiver<(Option<C>, M)>,
+}
+
+impl<C: TypedContext, M> ContextReceiver<C, M> {
+    /// Receive message and attach context, it was sent with.
+    ///
+    /// Guard is `None` if message was sent without context. Since [`ContextGuard`] is bound to
+    /// the thread, it should not be held across `.await` (use [`ContextReceiver::recv_raw`] and
+    /// [`FutureExt::with_opt`](crate::FutureExt::with_opt) to process message asynchronously).
+    pub async fn recv(&mut self) -> Option<(Option<ContextGuard<C>>, M)> {
+        let (context, msg) = self.recv_raw().await?;
+        Some((context.map(TypedContext::attach), msg))
+    }
+
+    /// Receive message alongside with context, it was sent with, without attaching it.
+    pub async fn recv_raw(&mut self) -> Option<(Option<C>, M)> {
+        self.inner.recv().await
+    }
+
+    /// Get underlying receiver.
+    pub fn into_inner(self) -> mpsc::Receiver<(Option<C>, M)> {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Job(u32);
+
+    crate::context!(Job);
+
+    #[tokio::test]
+    async fn propagate_through_channel() {
+        let (tx, mut rx) = super::channel::<Job, u32>(4);
+
+        tokio::spawn(async move {
+            tx.send(1).with(Job(10)).await.unwrap();
+            tx.send(2).await.unwrap();
+            tx.send_with(Some(Job(30)), 3).await.unwrap();
+        });
+
+        let (guard, msg) = rx.recv().await.unwrap();
+        assert_eq!((msg, Job::current()), (1, Some(Job(10))));
+        drop(guard);
+
+        let (guard, msg) = rx.recv().await.unwrap();
+        assert!(guard.is_none());
+        assert_eq!((msg, Job::current()), (2, None));
+
+        assert_eq!(rx.recv_raw().await, Some((Some(Job(30)), 3)));
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn send_to_closed_channel() {
+        let (tx, rx) = super::channel::<Job, u32>(1);
+        drop(rx);
+
+        assert_eq!(tx.send(1).with(Job(1)).await.unwrap_err().0, 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! Encoding of contexts into string maps (HTTP headers, message metadata, etc).
+
+use std::{collections::HashMap, fmt, marker::PhantomData};
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{ContextGuard, TypedContext};
+
+/// Trait for propagating contexts through string maps.
+///
+/// Values are encoded as JSON and stored under [`ContextCodec::KEY`].
+///
+/// Requires `serde` feature.
+///
+/// # Example:
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use serde::{Deserialize, Serialize};
+/// use tyco::{context, ContextCodec, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// struct TraceId(String);
+///
+/// context!(TraceId);
+///
+/// impl ContextCodec for TraceId {
+///     const KEY: &'static str = "trace-id";
+/// }
+///
+/// let mut headers = HashMap::new();
+/// TraceId("abc".into()).inject_into_map(&mut headers).unwrap();
+///
+/// assert_eq!(TraceId::extract_from_map(&headers), Some(TraceId("abc".into())));
+/// ```
+pub trait ContextCodec: TypedContext + Serialize + DeserializeOwned {
+    /// Key, under which value is stored in map.
+    const KEY: &'static str;
+
+    /// Encode value and insert it into map.
+    fn inject_into_map(&self, map: &mut HashMap<String, String>) -> serde_json::Result<()> {
+        map.insert(Self::KEY.into(), serde_json::to_string(self)?);
+        Ok(())
+    }
+
+    /// Decode value from map.
+    ///
+    /// `None` is returned if value is missing or can't be decoded.
+    fn extract_from_map(map: &HashMap<String, String>) -> Option<Self> {
+        map.get(Self::KEY)
+            .and_then(|x| serde_json::from_str(x).ok())
+    }
+}
+
+/// Serialized value of a context, which can be persisted and restored later (even by other process).
+///
+/// Checkpoint itself implements [`Serialize`] and [`Deserialize`] and is represented as serialized
+/// value of the context, so it can be embedded into other structures (like job descriptions in a
+/// queue). Value is kept as JSON value and is deserialized on each restore.
+///
+/// Requires `serde` feature.
+///
+/// # Example:
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use tyco::{context, ContextCheckpoint, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// struct TraceId(String);
+///
+/// context!(TraceId);
+///
+/// let checkpoint = TraceId("abc".into())
+///     .scoped(ContextCheckpoint::<TraceId>::capture)
+///     .unwrap()
+///     .unwrap();
+/// let stored = serde_json::to_string(&checkpoint).unwrap();
+///
+/// let checkpoint: ContextCheckpoint<TraceId> = serde_json::from_str(&stored).unwrap();
+/// let _guard = checkpoint.restore().unwrap();
+/// assert_eq!(TraceId::current(), Some(TraceId("abc".into())));
+/// ```
+pub struct ContextCheckpoint<T> {
+    value: Value,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: TypedContext + Serialize + DeserializeOwned> ContextCheckpoint<T> {
+    /// Serialize current value of the context.
+    ///
+    /// `None` is returned if no value set.
+    pub fn capture() -> serde_json::Result<Option<Self>> {
+        T::with_current_ref(|x| x.map(Self::new).transpose())
+    }
+
+    /// Serialize value.
+    pub fn new(value: &T) -> serde_json::Result<Self> {
+        Ok(Self {
+            value: serde_json::to_value(value)?,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Deserialize value.
+    pub fn value(&self) -> serde_json::Result<T> {
+        T::deserialize(&self.value)
+    }
+
+    /// Deserialize value and set it as the current context.
+    pub fn restore(&self) -> serde_json::Result<ContextGuard<T>> {
+        self.value().map(TypedContext::attach)
+    }
+}
+
+impl<T> Clone for ContextCheckpoint<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for ContextCheckpoint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ContextCheckpoint")
+            .field(&self.value)
+            .finish()
+    }
+}
+
+impl<T> PartialEq for ContextCheckpoint<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T> Serialize for ContextCheckpoint<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for ContextCheckpoint<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            value: Value::deserialize(deserializer)?,
+            _marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{ContextCheckpoint, ContextCodec};
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Tenant {
+        id: u64,
+        name: String,
+    }
+
+    crate::context!(Tenant);
+
+    impl ContextCodec for Tenant {
+        const KEY: &'static str = "tenant";
+    }
+
+    #[test]
+    fn round_trip() {
+        let tenant = Tenant {
+            id: 1,
+            name: "name".into(),
+        };
+
+        let mut map = HashMap::new();
+        tenant.inject_into_map(&mut map).unwrap();
+
+        assert_eq!(Tenant::extract_from_map(&map), Some(tenant));
+    }
+
+    #[test]
+    fn missing_or_invalid() {
+        let mut map = HashMap::new();
+        assert_eq!(Tenant::extract_from_map(&map), None);
+
+        map.insert("tenant".into(), "invalid".into());
+        assert_eq!(Tenant::extract_from_map(&map), None);
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        assert_eq!(ContextCheckpoint::<Tenant>::capture().unwrap(), None);
+
+        let tenant = Tenant {
+            id: 2,
+            name: "name".into(),
+        };
+        let checkpoint = tenant
+            .clone()
+            .scoped(ContextCheckpoint::<Tenant>::capture)
+            .unwrap()
+            .unwrap();
+
+        let stored = serde_json::to_string(&checkpoint).unwrap();
+        assert_eq!(stored, r#"{"id":2,"name":"name"}"#);
+
+        let checkpoint = serde_json::from_str::<ContextCheckpoint<Tenant>>(&stored).unwrap();
+        let guard = checkpoint.restore().unwrap();
+        assert_eq!(Tenant::current(), Some(tenant));
+
+        drop(guard);
+        assert_eq!(Tenant::current(), None);
+    }
+
+    #[test]
+    fn checkpoint_invalid() {
+        let checkpoint = serde_json::from_str::<ContextCheckpoint<Tenant>>("1").unwrap();
+
+        assert!(checkpoint.restore().is_err());
+        assert_eq!(Tenant::current(), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! [`crossbeam_channel`] channels, passing context alongside with messages.
+//!
+//! Requires `crossbeam` feature.
+//!
+//! # Example:
+//!
+//! ```
+//! use tyco::{context, crossbeam, TypedContext};
+//!
+//! #[derive(Clone, Debug, PartialEq)]
+//! struct RequestId(u64);
+//!
+//! context!(RequestId);
+//!
+//! let (tx, rx) = crossbeam::unbounded_with_context::<RequestId, &str>();
+//!
+//! std::thread::spawn(move || RequestId(1).scoped(|| tx.send("ping").unwrap()));
+//!
+//! let (guard, msg) = rx.recv().unwrap();
+//! assert_eq!(msg, "ping");
+//! assert_eq!(RequestId::current(), Some(RequestId(1)));
+//! drop(guard);
+//! ```
+
+use crossbeam_channel::{self as channel, RecvError, SendError};
+
+use crate::{ContextGuard, TypedContext};
+
+/// Create bounded channel, which passes current value of context `C` with each message.
+pub fn bounded_with_context<C: TypedContext, M>(
+    cap: usize,
+) -> (ContextSender<C, M>, ContextReceiver<C, M>) {
+    let (tx, rx) = channel::bounded(cap);
+    (ContextSender { inner: tx }, ContextReceiver { inner: rx })
+}
+
+/// Create unbounded channel, which passes current value of context `C` with each message.
+pub fn unbounded_with_context<C: TypedContext, M>() -> (ContextSender<C, M>, ContextReceiver<C, M>)
+{
+    let (tx, rx) = channel::unbounded();
+    (ContextSender { inner: tx }, ContextReceiver { inner: rx })
+}
+
+/// Sending half of the channel, created with [`bounded_with_context`] or
+/// [`unbounded_with_context`].
+#[derive(Debug)]
+pub struct ContextSender<C, M> {
+    inner: channel::Sender<(Option<C>, M)>,
+}
+
+impl<C: TypedContext, M> ContextSender<C, M> {
+    /// Send message with current value of the context, blocking if channel is full.
+    ///
+    /// If channel is disconnected, message is returned in error (context is dropped).
+    pub fn send(&self, msg: M) -> Result<(), SendError<M>> {
+        self.send_with(C::current(), msg)
+    }
+
+    /// Send message with explicitly specified value of the context.
+    ///
+    /// Useful when message already carries context or when it must be sent without context.
+    pub fn send_with(&self, context: Option<C>, msg: M) -> Result<(), SendError<M>> {
+        self.inner
+            .send((context, msg))
+            .map_err(|SendError((_, msg))| SendError(msg))
+    }
+
+    /// Get underlying sender.
+    pub fn into_inner(self) -> channel::Sender<(Option<C>, M)> {
+        self.inner
+    }
+}
+
+impl<C, M> Clone for ContextSender<C, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Receiving half of the channel, created with [`bounded_with_context`] or
+/// [`unbounded_with_context`].
+#[derive(Debug)]
+pub struct ContextReceiver<C, M> {
+    inner: channel::Receiver<(Option<C>, M)>,
+}
+
+impl<C: TypedContext, M> ContextReceiver<C, M> {
+    /// Receive message, blocking until it is available, and attach context, it was sent with.
+    ///
+    /// Guard is `None` if message was sent without context.
+    pub fn recv(&self) -> Result<(Option<ContextGuard<C>>, M), RecvError> {
+        let (context, msg) = self.recv_raw()?;
+        Ok((context.map(TypedContext::attach), msg))
+    }
+
+    /// Receive message alongside with context, it was sent with, without attaching it.
+    pub fn recv_raw(&self) -> Result<(Option<C>, M), RecvError> {
+        self.inner.recv()
+    }
+
+    /// Get underlying receiver.
+    pub fn into_inner(self) -> channel::Receiver<(Option<C>, M)> {
+        self.inner
+    }
+}
+
+impl<C, M> Clone for ContextReceiver<C, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Job(u32);
+
+    crate::context!(Job);
+
+    #[test]
+    fn propagate_through_channel() {
+        let (tx, rx) = super::bounded_with_context::<Job, u32>(4);
+
+        std::thread::spawn(move || {
+            Job(10).scoped(|| tx.send(1)).unwrap();
+            tx.send(2).unwrap();
+            tx.send_with(Some(Job(30)), 3).unwrap();
+        });
+
+        let (guard, msg) = rx.recv().unwrap();
+        assert_eq!((msg, Job::current()), (1, Some(Job(10))));
+        drop(guard);
+
+        let (guard, msg) = rx.recv().unwrap();
+        assert!(guard.is_none());
+        assert_eq!((msg, Job::current()), (2, None));
+
+        assert_eq!(rx.recv_raw(), Ok((Some(Job(30)), 3)));
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn send_to_disconnected_channel() {
+        let (tx, rx) = super::unbounded_with_context::<Job, u32>();
+        drop(rx);
+
+        assert_eq!(Job(1).scoped(|| tx.send(1)).unwrap_err().0, 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! Process-wide context storage, shared between all threads.
+
+use std::{cell::RefCell, marker::PhantomData, sync::Mutex};
+
+use crate::{ContextSlot, TlsBackend, TypedContext};
+
+/// Storage of context value, shared between all threads and protected by [`Mutex`].
+///
+/// Used by [`context_thread_safe!`](crate::context_thread_safe). Value, attached on one thread, is
+/// visible on every other thread, and guards restore previous values globally, so this storage is
+/// intended for tests and other controlled environments, where context is not changed concurrently.
+/// Lock is held while storage is accessed, so accessing the same context from inside of
+/// [`TypedContext::with_current_ref`] deadlocks.
+///
+/// Since guards of different threads can be dropped in any order, values are never borrowed by the
+/// storage: futures and other wrappers attach clones of their values on each poll.
+pub struct GlobalSlot<T: 'static>(Mutex<RefCell<ContextSlot<T>>>);
+
+impl<T> GlobalSlot<T> {
+    /// Create empty storage.
+    pub const fn new() -> Self {
+        Self(Mutex::new(RefCell::new(ContextSlot::new())))
+    }
+}
+
+impl<T> Default for GlobalSlot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + Sync> TlsBackend<T> for GlobalSlot<T> {
+    const SHARED: bool = true;
+
+    fn try_with<R>(&'static self, f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R) -> Option<R> {
+        Some(f(&self.0.lock().unwrap_or_else(|e| e.into_inner())))
+    }
+}
+
+/// Macro for implementing typed context with storage, shared between all threads.
+///
+/// Same as [`context!`](crate::context), but uses [`GlobalSlot`] instead of thread-local storage, so
+/// context, set in the test thread, is visible in threads of the runtime (like worker threads of
+/// `#[tokio::test(flavor = "multi_thread")]`). Every access takes a lock. Type must be `Send + Sync`.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context_thread_safe, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct FeatureFlags(Vec<&'static str>);
+///
+/// context_thread_safe!(FeatureFlags);
+///
+/// let _guard = FeatureFlags(vec!["new-ui"]).attach();
+///
+/// let flags = std::thread::spawn(FeatureFlags::current).join().unwrap();
+/// assert_eq!(flags, Some(FeatureFlags(vec!["new-ui"])));
+/// ```
+#[macro_export]
+macro_rules! context_thread_safe {
+    ($name:path) => {
+        const _: () = {
+            $crate::context_thread_safe!($name, CURRENT_CONTEXT_VALUE);
+        };
+    };
+    ($name:path, $storage:ident) => {
+        $crate::context!($name, static $storage: $crate::GlobalSlot<$name> = $crate::GlobalSlot::new());
+    };
+}
+
+/// Helper for injecting context into tests of sync code.
+///
+/// Intended for contexts, defined with [`context_thread_safe!`](crate::context_thread_safe), so value
+/// is visible to all threads, spawned by the tested code. Since value is shared, tests, mocking
+/// the same context, must not run concurrently.
+pub struct MockContext<T>(PhantomData<fn() -> T>);
+
+impl<T: TypedContext> MockContext<T> {
+    /// Set value as current context for the duration of the test function.
+    ///
+    /// Previous value is restored after function returns, even if it panics.
+    pub fn with<R>(value: T, test_fn: impl FnOnce() -> R) -> R {
+        value.scoped(test_fn)
+    }
+
+    /// Clear context for the duration of the test function.
+    pub fn without<R>(test_fn: impl FnOnce() -> R) -> R {
+        let _guard = crate::ClearGuard::<T>::new();
+        test_fn()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+
+    use super::MockContext;
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Config(&'static str);
+
+    crate::context_thread_safe!(Config);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Owner(String);
+
+    crate::context_thread_safe!(Owner);
+
+    #[test]
+    fn shared_between_threads() {
+        let current = || std::thread::spawn(Config::current).join().unwrap();
+
+        MockContext::with(Config("mock"), || {
+            assert_eq!(current(), Some(Config("mock")));
+            MockContext::<Config>::without(|| assert_eq!(current(), None));
+            assert_eq!(Config::nesting_depth(), 1);
+        });
+
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn futures_polled_on_different_threads() {
+        let barriers = Arc::new([Barrier::new(2), Barrier::new(2), Barrier::new(2)]);
+
+        // Polls overlap: first one ends (and its future completes) while second one is still running,
+        // so second one restores value of the completed future.
+        let first = std::thread::spawn({
+            let barriers = barriers.clone();
+            move || {
+                futures::executor::block_on(
+                    async {
+                        barriers[0].wait();
+                        barriers[1].wait();
+                    }
+                    .with(Owner("first".into())),
+                );
+                barriers[2].wait();
+            }
+        });
+        let second = std::thread::spawn(move || {
+            barriers[0].wait();
+            futures::executor::block_on(
+                async {
+                    barriers[1].wait();
+                    barriers[2].wait();
+                }
+                .with(Owner("second".into())),
+            );
+        });
+        first.join().unwrap();
+        second.join().unwrap();
+
+        assert_eq!(Owner::take(), Some(Owner("first".into())));
+    }
+}
@@ -0,0 +1,184 @@
+//! Per-thread log of recent context transitions.
+
+use std::{cell::RefCell, collections::VecDeque, time::Instant};
+
+/// Maximum number of events, kept in history of each thread.
+pub const CONTEXT_HISTORY_CAPACITY: usize = 128;
+
+thread_local! {
+    static HISTORY: RefCell<VecDeque<ContextEvent>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Kind of context transition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContextAction {
+    /// Value was attached.
+    Attach,
+    /// Value was detached and previous value was restored.
+    Detach,
+}
+
+/// Context transition, recorded in history of current thread.
+///
+/// Requires `debug-history` feature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContextEvent {
+    /// Name of the context type.
+    pub type_name: &'static str,
+    /// Kind of the transition.
+    pub action: ContextAction,
+    /// When transition happened.
+    pub timestamp: Instant,
+}
+
+/// Get recent context transitions of current thread, from oldest to newest.
+///
+/// Transitions are recorded whenever value is attached or restored by any guard, including values,
+/// set on each poll of a future (like with [`FutureExt::with`](crate::FutureExt::with)). Only last
+/// [`CONTEXT_HISTORY_CAPACITY`] events are kept.
+///
+/// Requires `debug-history` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{clear_context_history, context, context_history, ContextAction, TypedContext};
+///
+/// #[derive(Clone, Debug)]
+/// struct TraceId(u64);
+///
+/// context!(TraceId);
+///
+/// clear_context_history();
+/// TraceId(1).scoped(|| {});
+///
+/// let actions = context_history().into_iter().map(|x| x.action).collect::<Vec<_>>();
+/// assert_eq!(actions, [ContextAction::Attach, ContextAction::Detach]);
+/// ```
+pub fn context_history() -> Vec<ContextEvent> {
+    HISTORY
+        .try_with(|history| history.borrow().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Clear history of context transitions of current thread.
+///
+/// Requires `debug-history` feature.
+pub fn clear_context_history() {
+    let _ = HISTORY.try_with(|history| history.borrow_mut().clear());
+}
+
+pub(crate) fn record<T>(action: ContextAction) {
+    let event = ContextEvent {
+        type_name: std::any::type_name::<T>(),
+        action,
+        timestamp: Instant::now(),
+    };
+
+    let _ = HISTORY.try_with(|history| {
+        let mut history = history.borrow_mut();
+        if history.len() == CONTEXT_HISTORY_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back(event);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear_context_history, context_history, ContextAction, CONTEXT_HISTORY_CAPACITY};
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RequestId(u64);
+
+    crate::context!(RequestId, REQUEST_ID_CONTEXT);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct UserId(u64);
+
+    crate::context!(UserId, USER_ID_CONTEXT);
+
+    fn history() -> Vec<(&'static str, ContextAction)> {
+        context_history()
+            .into_iter()
+            .map(|x| (x.type_name.rsplit("::").next().unwrap(), x.action))
+            .collect()
+    }
+
+    #[test]
+    fn records_transitions() {
+        clear_context_history();
+
+        let request_guard = RequestId(1).attach();
+        UserId(2).scoped(|| {});
+        let value = RequestId(3).attach().into_value();
+        drop(request_guard);
+
+        assert_eq!(value, RequestId(3));
+        assert_eq!(
+            history(),
+            [
+                ("RequestId", ContextAction::Attach),
+                ("UserId", ContextAction::Attach),
+                ("UserId", ContextAction::Detach),
+                ("RequestId", ContextAction::Attach),
+                ("RequestId", ContextAction::Detach),
+                ("RequestId", ContextAction::Detach),
+            ]
+        );
+
+        let events = context_history();
+        assert!(events.windows(2).all(|x| x[0].timestamp <= x[1].timestamp));
+
+        clear_context_history();
+        assert!(context_history().is_empty());
+    }
+
+    #[test]
+    fn records_polls() {
+        clear_context_history();
+
+        futures::executor::block_on(async {}.with(RequestId(1)));
+        let value = RequestId(2).attach().into_value();
+
+        assert_eq!(value, RequestId(2));
+        assert_eq!(
+            history(),
+            [
+                ("RequestId", ContextAction::Attach),
+                ("RequestId", ContextAction::Detach),
+                ("RequestId", ContextAction::Attach),
+                ("RequestId", ContextAction::Detach),
+            ]
+        );
+    }
+
+    #[test]
+    fn upgraded_guard() {
+        clear_context_history();
+
+        let value = RequestId(1);
+        drop(unsafe { value.attach_ref() }.upgrade());
+
+        assert_eq!(
+            history(),
+            [
+                ("RequestId", ContextAction::Attach),
+                ("RequestId", ContextAction::Detach),
+            ]
+        );
+    }
+
+    #[test]
+    fn capacity() {
+        clear_context_history();
+        for i in 0..CONTEXT_HISTORY_CAPACITY {
+            RequestId(i as u64).scoped(|| {});
+        }
+
+        let events = context_history();
+        assert_eq!(events.len(), CONTEXT_HISTORY_CAPACITY);
+        assert_eq!(events[0].action, ContextAction::Attach);
+    }
+}
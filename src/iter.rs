@@ -0,0 +1,109 @@
+//! Context propagation for [`Iterator`]s.
+
+use core::iter::FusedIterator;
+
+use crate::TypedContext;
+
+/// Wrapper for an iterator, setting context while next item is produced.
+///
+/// Closures of lazy adapters (like [`Iterator::map`] or [`Iterator::filter`]), wrapped by it, see
+/// the context, even if iterator is consumed on another thread.
+#[derive(Clone, Debug)]
+pub struct WithContextIter<I, T> {
+    inner: I,
+    value: Option<T>,
+}
+
+impl<I, T> WithContextIter<I, T> {
+    /// Get inner iterator.
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: Iterator, T: TypedContext> Iterator for WithContextIter<I, T> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let _guard = self.value.as_ref().map(|x| unsafe { x.attach_ref() });
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<I: DoubleEndedIterator, T: TypedContext> DoubleEndedIterator for WithContextIter<I, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let _guard = self.value.as_ref().map(|x| unsafe { x.attach_ref() });
+        self.inner.next_back()
+    }
+}
+
+impl<I: ExactSizeIterator, T: TypedContext> ExactSizeIterator for WithContextIter<I, T> {}
+
+impl<I: FusedIterator, T: TypedContext> FusedIterator for WithContextIter<I, T> {}
+
+/// Extension trait allowing to attach context to iterators.
+pub trait IteratorExt: Sized {
+    /// Set value as context for iterator.
+    fn with<T>(self, value: T) -> WithContextIter<Self, T> {
+        WithContextIter {
+            inner: self,
+            value: Some(value),
+        }
+    }
+
+    /// Set optional value as context for iterator.
+    ///
+    /// Primarily used with return value of [`TypedContext::current`].
+    fn with_opt<T>(self, value: Option<T>) -> WithContextIter<Self, T> {
+        WithContextIter { inner: self, value }
+    }
+
+    /// Take current context and set is as context for an iterator.
+    ///
+    /// Basically it is `self.with_opt(T::current())`.
+    fn with_current<T: TypedContext>(self) -> WithContextIter<Self, T> {
+        self.with_opt(T::current())
+    }
+}
+
+impl<I: Sized + Iterator> IteratorExt for I {}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::IteratorExt;
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tag(u32);
+
+    crate::context!(Tag);
+
+    #[test]
+    fn lazy_adapters() {
+        let iter = (0..4u32)
+            .filter(|x| Tag::current_map(|tag| x % 2 == tag.0).unwrap_or(false))
+            .map(|x| (x, Tag::current()))
+            .with(Tag(1));
+
+        assert_eq!(Tag::current(), None);
+        assert_eq!(
+            iter.collect::<Vec<_>>(),
+            vec![(1, Some(Tag(1))), (3, Some(Tag(1)))]
+        );
+        assert_eq!(Tag::current(), None);
+    }
+
+    #[test]
+    fn scoped_threads() {
+        let _guard = Tag(2).attach();
+        let iter = (0..3).map(|_| Tag::current()).with_current::<Tag>().rev();
+
+        let items = std::thread::scope(|s| s.spawn(|| iter.collect::<Vec<_>>()).join().unwrap());
+
+        assert_eq!(items, vec![Some(Tag(2)); 3]);
+    }
+}
@@ -0,0 +1,144 @@
+//! [`tower`](https://docs.rs/tower) middleware for attaching contexts to services.
+
+use std::{
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{FutureExt, TypedContext, WithContext};
+
+/// Layer, which extracts context from request and attaches it to the future of inner service.
+///
+/// Requires `tower` feature.
+///
+/// # Example:
+///
+/// ```
+/// use axum::http::Request;
+/// use tyco::{context, ContextLayer, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(String);
+///
+/// context!(TraceId);
+///
+/// let layer = ContextLayer::new(|req: &Request<()>| {
+///     req.headers()
+///         .get("trace-id")
+///         .and_then(|x| x.to_str().ok())
+///         .map(|x| TraceId(x.to_owned()))
+/// });
+/// ```
+pub struct ContextLayer<T, E> {
+    extractor: E,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, E> ContextLayer<T, E> {
+    /// Create layer with function, extracting context value from request.
+    pub fn new<R>(extractor: E) -> Self
+    where
+        E: Fn(&R) -> Option<T>,
+    {
+        Self {
+            extractor,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, E: Clone> Clone for ContextLayer<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            extractor: self.extractor.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, T, E: Clone> Layer<S> for ContextLayer<T, E> {
+    type Service = ContextService<S, T, E>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ContextService {
+            inner,
+            extractor: self.extractor.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Service, created by [`ContextLayer`].
+pub struct ContextService<S, T, E> {
+    inner: S,
+    extractor: E,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<S: Clone, T, E: Clone> Clone for ContextService<S, T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            extractor: self.extractor.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, T, E, R> Service<R> for ContextService<S, T, E>
+where
+    S: Service<R>,
+    T: TypedContext,
+    E: Fn(&R) -> Option<T>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = WithContext<S::Future, T>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let value = (self.extractor)(&req);
+        self.inner.call(req).with_opt(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::Layer;
+
+    use super::ContextLayer;
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TraceId(String);
+
+    crate::context!(TraceId);
+
+    fn assert_clone_send_sync<T: Clone + Send + Sync>(_: &T) {}
+
+    #[tokio::test]
+    async fn attach_extracted() {
+        let layer =
+            ContextLayer::new(|req: &&str| req.strip_prefix("trace:").map(|x| TraceId(x.into())));
+        assert_clone_send_sync(&layer);
+
+        let service = layer.layer(service_fn(|_: &str| async {
+            Ok::<_, Infallible>(TraceId::current())
+        }));
+
+        assert_eq!(
+            service.clone().oneshot("trace:abc").await.unwrap(),
+            Some(TraceId("abc".into()))
+        );
+        assert_eq!(service.oneshot("abc").await.unwrap(), None);
+    }
+}
@@ -14,6 +14,7 @@
 //! like distinguishing HTTP requests.
 //!
 //! ```no_run
+//! # #[cfg(feature = "std")] {
 //! use tyco::{context, FutureExt, TypedContext};
 //!
 //! mod trace_id {
@@ -33,238 +34,4213 @@
 //!     .with_current::<trace_id::TraceId>()
 //!     .with(t.clone()),
 //! );
+//! # }
 //! ```
+//!
+//! # Features
+//!
+//! `std` feature is enabled by default. Without it crate is `no_std` (but requires `alloc`) and contexts
+//! must be defined with custom [`TlsBackend`].
+//!
+//! `debug-history` feature records recent context transitions of each thread, which can be read with
+//! `context_history`. It is intended for debugging and tests and has no overhead when disabled.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-use std::{
-    borrow::Cow,
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc};
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::{
     cell::RefCell,
+    fmt,
     future::Future,
     marker::PhantomData,
+    ops::{Deref, DerefMut},
     pin::Pin,
     task::{Context, Poll},
-    thread::LocalKey,
 };
+#[cfg(feature = "std")]
+use std::thread::LocalKey;
 
+#[cfg(feature = "futures")]
+use futures_core::future::FusedFuture;
 use pin_project_lite::pin_project;
 
-/// Trait for interaction with typed contexts.
-pub trait TypedContext: Clone + 'static {
-    const TLS: LocalKey<RefCell<Option<Cow<'static, Self>>>>;
+#[cfg(feature = "tokio")]
+mod blocking;
+#[cfg(feature = "std")]
+pub mod builtins;
+mod bundle;
+mod cell;
+mod chain;
+#[cfg(feature = "tokio")]
+mod channel;
+#[cfg(feature = "serde")]
+mod codec;
+#[cfg(feature = "crossbeam")]
+pub mod crossbeam;
+#[cfg(feature = "std")]
+mod global;
+#[cfg(feature = "debug-history")]
+mod history;
+mod iter;
+#[cfg(feature = "tower")]
+mod layer;
+#[cfg(feature = "std")]
+mod observer;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "tokio")]
+mod shared;
+#[cfg(feature = "futures")]
+mod sink;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "tracing")]
+mod span;
+#[cfg(feature = "std")]
+pub mod span_bag;
+#[cfg(feature = "std")]
+mod stack;
+#[cfg(feature = "futures")]
+mod stream;
+#[cfg(feature = "std")]
+mod tag;
+#[cfg(feature = "std")]
+mod thread;
+#[cfg(feature = "tokio")]
+mod timeout;
+mod waker;
 
-    /// Get clone of current value of the context.
-    ///
-    /// `None` is returned if no value set.
-    fn current() -> Option<Self> {
-        Self::TLS.with(|v| v.borrow().clone()).map(Cow::into_owned)
-    }
+#[cfg(feature = "tokio")]
+pub use blocking::{spawn_blocking_with_context, ContextBlockingBuilder};
+pub use bundle::{BundleGuard, ContextBundle, WithContextBundle};
+pub use cell::{ContextCell, WithContextCell};
+pub use chain::{chain_context, OrContext};
+#[cfg(feature = "tokio")]
+pub use channel::{channel, ContextReceiver, ContextSender};
+#[cfg(feature = "serde")]
+pub use codec::{ContextCheckpoint, ContextCodec};
+#[cfg(feature = "std")]
+pub use global::{GlobalSlot, MockContext};
+#[cfg(feature = "debug-history")]
+pub use history::{
+    clear_context_history, context_history, ContextAction, ContextEvent, CONTEXT_HISTORY_CAPACITY,
+};
+pub use iter::{IteratorExt, WithContextIter};
+#[cfg(feature = "tower")]
+pub use layer::{ContextLayer, ContextService};
+#[cfg(feature = "std")]
+pub use observer::ContextObserver;
+#[cfg(feature = "tracing")]
+pub use observer::TracingObserver;
+#[cfg(feature = "rayon")]
+pub use parallel::{install_context, ParallelIteratorExt, WithContextParIter};
+#[cfg(feature = "tokio")]
+pub use shared::{SharedContext, SharedContextGuard};
+#[cfg(feature = "std")]
+pub use snapshot::{ContextSnapshot, SnapshotGuard, WithCaptured};
+#[cfg(feature = "tracing")]
+pub use span::{SpanContext, SpanContextExt, TracingSpanContext, WithSpan};
+#[cfg(feature = "std")]
+pub use stack::{StackGuard, StackRefGuard, StackedContext, WithContextStack};
+#[cfg(feature = "std")]
+pub use tag::{tag, TagBackend, TagContext};
+#[cfg(feature = "std")]
+pub use thread::{spawn_with_context, ThreadContextPropagator};
+#[cfg(feature = "tokio")]
+pub use timeout::TimeoutGuard;
+pub use waker::{ContextAwareWaker, WithContextWaker};
 
-    /// Set value as the current context.
-    ///
-    /// It will live as long as returned guard is alive. Previous value is stored
-    /// inside guard and will be restored on drop.
-    fn attach(self) -> ContextGuard<Self> {
-        let previous_value = Self::TLS
-            .try_with(|current| current.replace(Some(Cow::Owned(self))))
-            .ok();
+#[cfg(feature = "futures")]
+pub use sink::SinkExt;
+#[cfg(feature = "futures")]
+pub use stream::{StreamExt, WithContextStream};
 
-        ContextGuard {
-            previous_value,
-            _marker: PhantomData,
+/// Derive macro implementing [`TypedContext`] trait.
+///
+/// Alternative to [`context!`] macro, which generates uniquely-named TLS variable, so any number of
+/// context types can be defined in the same module. TLS variable is named `__TYCO_CONTEXT_` followed
+/// by name of the type in `SCREAMING_SNAKE_CASE` (`TraceId` -> `__TYCO_CONTEXT_TRACE_ID`).
+///
+/// Type must implement [`Clone`]. Generic types are not supported, since thread-local storage can't be
+/// generic.
+///
+/// Requires `derive` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::TypedContext;
+///
+/// #[derive(Clone, Debug, PartialEq, TypedContext)]
+/// struct TraceId(String);
+///
+/// #[derive(Clone, Debug, PartialEq, TypedContext)]
+/// struct UserId(u64);
+///
+/// let _trace_guard = TraceId("trace".into()).attach();
+/// let _user_guard = UserId(1).attach();
+///
+/// assert_eq!(TraceId::current(), Some(TraceId("trace".into())));
+/// assert_eq!(UserId::current(), Some(UserId(1)));
+/// ```
+#[cfg(feature = "derive")]
+pub use tyco_derive::TypedContext;
+
+/// Attribute macro, propagating current values of listed contexts into function body.
+///
+/// For `async fn` body is wrapped with [`FutureExt::with_current`] for each type, so values, current
+/// at the first poll, are attached on each poll of the body (even if it is polled from the other
+/// task or thread). For sync functions values are re-attached with [`TypedContext::attach`] and
+/// restored on return, so changes, made by the body with [`TypedContext::replace`], don't leak to
+/// the caller.
+///
+/// Requires `derive` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{with_context, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq, TypedContext)]
+/// struct TraceId(String);
+///
+/// #[derive(Clone, Debug, PartialEq, TypedContext)]
+/// struct UserId(u64);
+///
+/// #[with_context(TraceId, UserId)]
+/// async fn handle() -> Option<UserId> {
+///     tokio::spawn(async { UserId::current() }).await.unwrap()
+/// }
+/// ```
+#[cfg(feature = "derive")]
+pub use tyco_derive::with_context;
+
+/// Attribute macro, attaching or clearing listed contexts for the duration of sync function call.
+///
+/// `push = Type` re-attaches current value of the context (so changes, made by the function with
+/// [`TypedContext::replace`], are not visible to the caller) and `clear = Type` clears the context
+/// while function is executed. Any number of contexts can be listed. Previous values are restored by
+/// guards, so early returns and panics are handled. Can't be applied to `async fn`, use
+/// [`with_context`](macro@with_context) or [`FutureExt`] for them.
+///
+/// Requires `derive` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context_fn, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq, TypedContext)]
+/// struct TraceId(String);
+///
+/// #[derive(Clone, Debug, PartialEq, TypedContext)]
+/// struct UserId(u64);
+///
+/// #[context_fn(push = TraceId, clear = UserId)]
+/// fn background() -> Option<UserId> {
+///     TraceId::replace(TraceId("background".into()));
+///     UserId::current()
+/// }
+///
+/// let _trace_guard = TraceId("request".into()).attach();
+/// let _user_guard = UserId(1).attach();
+///
+/// assert_eq!(background(), None);
+/// assert_eq!(TraceId::current(), Some(TraceId("request".into())));
+/// assert_eq!(UserId::current(), Some(UserId(1)));
+/// ```
+#[cfg(feature = "derive")]
+pub use tyco_derive::context_fn;
+
+/// Attribute macro implementing [`TypedContext`] trait, alternative to [`context!`].
+///
+/// Same as [`TypedContext`](derive@TypedContext) derive macro, but additionally accepts default value
+/// of the context (`#[typed_context(default = expr)]`, see [`context!`] for details). Since impl is
+/// generated by procedural macro, it is visible to IDEs and errors (like missing [`Clone`]
+/// implementation) are reported at the type.
+///
+/// Requires `derive` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{typed_context, TypedContext};
+///
+/// #[typed_context(default = LogLevel::Info)]
+/// #[derive(Clone, Debug, PartialEq)]
+/// enum LogLevel {
+///     Info,
+///     Debug,
+/// }
+///
+/// #[typed_context]
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(String);
+///
+/// assert_eq!(LogLevel::current(), Some(LogLevel::Info));
+///
+/// let _guard = LogLevel::Debug.attach();
+/// assert_eq!(LogLevel::current(), Some(LogLevel::Debug));
+/// assert_eq!(TraceId::current(), None);
+/// ```
+#[cfg(feature = "derive")]
+pub use tyco_derive::typed_context;
+
+/// Check, that context type implements [`Clone`]. Used by procedural macros.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "context type `{Self}` must implement `Clone`",
+    label = "did you forget `#[derive(Clone)]`?"
+)]
+pub trait __ContextClone: Clone {}
+
+#[cfg(feature = "derive")]
+impl<T: Clone> __ContextClone for T {}
+
+/// Clear context until returned guard is dropped. Used by [`context_fn`](macro@context_fn).
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub fn __clear_context<T: TypedContext>() -> impl Drop {
+    ClearGuard::<T>::new()
+}
+
+/// Value of a context, stored in TLS.
+///
+/// Owned values are shared between TLS and [`ContextGuard`], so guard can provide access to the attached
+/// value without cloning it.
+#[derive(Debug)]
+pub enum ContextValue<T: 'static> {
+    /// Value, set with [`TypedContext::attach`].
+    Owned(Arc<T>),
+    /// Value, set with [`TypedContext::attach_ref`].
+    Borrowed(&'static T),
+    /// Default value, set on initialization of TLS (see `default` form of [`context!`]).
+    Default(Arc<T>),
+}
+
+impl<T: Clone> ContextValue<T> {
+    /// Extract owned value, cloning it if it is borrowed or shared.
+    pub fn into_owned(self) -> T {
+        match self {
+            Self::Owned(x) | Self::Default(x) => Arc::unwrap_or_clone(x),
+            Self::Borrowed(x) => x.clone(),
         }
     }
 
-    /// Set reference to a value as current context.
-    ///
-    /// This function is mainly used for [`FutureExt`] implementation and should
-    /// not be used by user (or used with great caution !!!).
-    ///
-    /// # Safety
+    /// Convert borrowed value into owned one, cloning it. Other values are returned as is.
+    fn into_shared(self) -> Self {
+        match self {
+            Self::Borrowed(x) => Self::Owned(Arc::new(x.clone())),
+            x => x,
+        }
+    }
+
+    /// Get mutable reference to the value, cloning it if it is borrowed or shared.
     ///
-    /// Incorrect usage might lead to segfault.
-    unsafe fn attach_ref(&self) -> ContextRefGuard<Self> {
-        let static_ref: &'static Self = unsafe { &*(self as *const Self) };
-        let previous_value = Self::TLS
-            .try_with(|current| current.replace(Some(Cow::Borrowed(static_ref))))
-            .ok();
+    /// Modified default value is no longer considered default.
+    pub fn to_mut(&mut self) -> &mut T {
+        match self {
+            Self::Borrowed(x) => *self = Self::Owned(Arc::new(x.clone())),
+            Self::Default(x) => *self = Self::Owned(x.clone()),
+            Self::Owned(_) => {}
+        }
 
-        ContextRefGuard {
-            previous_value,
-            _marker: PhantomData,
+        match self {
+            Self::Owned(x) => Arc::make_mut(x),
+            Self::Borrowed(_) | Self::Default(_) => unreachable!(),
         }
     }
 }
 
-/// Guard, created with [`TypedContext::attach`], keeping value as current context.
-///
-/// On drop it will restore previous value.
-pub struct ContextGuard<T: TypedContext> {
-    previous_value: Option<Option<Cow<'static, T>>>,
-    _marker: PhantomData<*const ()>,
+impl<T> ContextValue<T> {
+    /// Check if value is default value of the context.
+    pub fn is_default(&self) -> bool {
+        matches!(self, Self::Default(_))
+    }
 }
 
-impl<T: TypedContext> Drop for ContextGuard<T> {
-    fn drop(&mut self) {
-        if let Some(previous_value) = self.previous_value.take() {
-            let _ = T::TLS.try_with(|current| current.replace(previous_value));
+impl<T> Clone for ContextValue<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Owned(x) => Self::Owned(x.clone()),
+            Self::Borrowed(x) => Self::Borrowed(x),
+            Self::Default(x) => Self::Default(x.clone()),
         }
     }
 }
 
-/// Guard, created with [`TypedContext::attach_ref`], keeping value as current context.
-///
-/// On drop it will restore previous value.
-pub struct ContextRefGuard<'a, T: TypedContext> {
-    previous_value: Option<Option<Cow<'static, T>>>,
-    _marker: PhantomData<&'a ()>,
-}
+impl<T> Deref for ContextValue<T> {
+    type Target = T;
 
-impl<'a, T: TypedContext> Drop for ContextRefGuard<'a, T> {
-    fn drop(&mut self) {
-        if let Some(previous_value) = self.previous_value.take() {
-            let _ = T::TLS.try_with(|current| current.replace(previous_value));
+    fn deref(&self) -> &T {
+        match self {
+            Self::Owned(x) | Self::Default(x) => x,
+            Self::Borrowed(x) => x,
         }
     }
 }
 
-pin_project! {
-    /// Wrapper for a future, responsible for managing its context.
-    #[derive(Clone, Debug)]
-    pub struct WithContext<F, T> {
-        #[pin]
-        inner: F,
-        value: Option<T>,
-    }
+/// Counters of attaches and detaches of some context.
+#[cfg(feature = "metrics")]
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct ContextCounters {
+    attach: AtomicU64,
+    detach: AtomicU64,
 }
 
-impl<F: Future, T: TypedContext> Future for WithContext<F, T> {
-    type Output = F::Output;
+#[cfg(feature = "metrics")]
+impl ContextCounters {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        Self {
+            attach: AtomicU64::new(0),
+            detach: AtomicU64::new(0),
+        }
+    }
+}
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
+/// Slot in TLS, holding current value of a context.
+///
+/// Dereferences to the current value. Besides the value it tracks number of guards, alive on the
+/// current thread, and version of the value, which is incremented on each change.
+#[derive(Debug)]
+pub struct ContextSlot<T: 'static> {
+    value: Option<ContextValue<T>>,
+    depth: usize,
+    version: u64,
+}
 
-        if let Some(ref x) = this.value {
-            let _guard = unsafe { x.attach_ref() };
-            this.inner.poll(cx)
-        } else {
-            this.inner.poll(cx)
+impl<T> ContextSlot<T> {
+    /// Create empty slot.
+    pub const fn new() -> Self {
+        Self {
+            value: None,
+            depth: 0,
+            version: 0,
         }
     }
-}
 
-/// Extension trait allowing to attach context to futures.
-pub trait FutureExt: Sized {
-    /// Set value as context for future.
-    fn with<T>(self, value: T) -> WithContext<Self, T> {
-        WithContext {
-            inner: self,
-            value: Some(value),
+    /// Create slot with default value of the context.
+    pub fn with_default(value: T) -> Self {
+        Self {
+            value: Some(ContextValue::Default(Arc::new(value))),
+            depth: 0,
+            version: 0,
         }
     }
 
-    /// Set optional value as context for future.
-    ///
-    /// Primarily used with return value of [`TypedContext::current`].
-    fn with_opt<T>(self, value: Option<T>) -> WithContext<Self, T> {
-        WithContext { inner: self, value }
+    /// Get number of guards, which are currently alive.
+    pub fn depth(&self) -> usize {
+        self.depth
     }
 
-    /// Take current context and set is as context for a future.
+    /// Get version of the current value.
     ///
-    /// Basically it is `self.with_opt(T::current())`.
-    fn with_current<T: TypedContext>(self) -> WithContext<Self, T> {
-        self.with_opt(T::current())
+    /// Version is incremented on each change of the value, including attaching and restoring previous
+    /// values by guards. Any mutable access to the value is considered a change.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Replace current value, without changing number of guards.
+    pub fn replace(&mut self, value: Option<ContextValue<T>>) -> Option<ContextValue<T>> {
+        self.version = self.version.wrapping_add(1);
+        core::mem::replace(&mut self.value, value)
+    }
+
+    /// Set value, attached by a guard, returning previous one.
+    fn attach(&mut self, value: ContextValue<T>) -> Option<ContextValue<T>> {
+        self.depth += 1;
+        self.replace(Some(value))
+    }
+
+    /// Restore previous value on drop of a guard, returning current one.
+    fn detach(&mut self, previous_value: Option<ContextValue<T>>) -> Option<ContextValue<T>> {
+        self.depth = self.depth.saturating_sub(1);
+        self.replace(previous_value)
     }
 }
 
-impl<T: Sized + Future<Output = O>, O> FutureExt for T {}
+impl<T> Default for ContextSlot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-/// Macro for implementing typed context.
-///
-/// This macro will generate impmenetation of [`TypedContext`] trait
-/// for your type alongside with necessary TLS definitions. Macro accept
-/// single argument - path to type.
-///
-/// # Note
+impl<T> Deref for ContextSlot<T> {
+    type Target = Option<ContextValue<T>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for ContextSlot<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.version = self.version.wrapping_add(1);
+        &mut self.value
+    }
+}
+
+/// Storage of the current value of a context.
 ///
-/// Macro can be used only once in one module, because it have 'static' names for TLS variable. This is
-/// done to keep this macro declarative.
+/// With `std` feature it is implemented for [`LocalKey`], which is used by [`context!`] macro by
+/// default. For other environments (like `no_std`) custom implementation can be provided.
 ///
 /// # Example:
 ///
-/// ```no_run
-/// use tyco::{context, FutureExt, TypedContext};
+/// ```
+/// use core::cell::RefCell;
 ///
-/// mod trace_id {
-///     use super::*;
+/// use tyco::{context, ContextSlot, TlsBackend, TypedContext};
 ///
-///     #[derive(Clone, Default, Debug, PartialEq)]
-///     pub struct TraceId(String);
+/// /// Storage for single-threaded environment.
+/// pub struct SingleThreaded<T: 'static>(RefCell<ContextSlot<T>>);
 ///
-///     tyco::context!(TraceId);
+/// // SAFETY: there is only one thread.
+/// unsafe impl<T> Sync for SingleThreaded<T> {}
+///
+/// impl<T> TlsBackend<T> for SingleThreaded<T> {
+///     fn try_with<R>(
+///         &'static self,
+///         f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R,
+///     ) -> Option<R> {
+///         Some(f(&self.0))
+///     }
 /// }
-#[macro_export]
-macro_rules! context {
-    ($name:path) => {
-        thread_local! {
-            static CURRENT_CONTEXT_VALUE: std::cell::RefCell<Option<std::borrow::Cow<'static, $name>>> =
-                std::cell::RefCell::new(None);
-        }
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct RequestId(u64);
+///
+/// context!(RequestId, static REQUEST_ID: SingleThreaded<RequestId> = SingleThreaded(RefCell::new(ContextSlot::new())));
+///
+/// let _guard = RequestId(1).attach();
+/// assert_eq!(RequestId::current(), Some(RequestId(1)));
+/// ```
+pub trait TlsBackend<T: 'static> {
+    /// Whether slot is shared between threads.
+    ///
+    /// References, attached with [`TypedContext::attach_ref`], are valid only until the guard is
+    /// dropped, which is guaranteed to happen in order only within a single thread. For shared slots
+    /// guards of different threads can be dropped in any order, so values are cloned instead.
+    const SHARED: bool = false;
 
-        impl $crate::TypedContext for $name {
-            const TLS: std::thread::LocalKey<std::cell::RefCell<Option<std::borrow::Cow<'static, Self>>>> =
-                CURRENT_CONTEXT_VALUE;
-        }
-    };
+    /// Call function with slot, holding current value.
+    ///
+    /// `None` is returned if slot is not accessible (for example, thread is being destroyed).
+    fn try_with<R>(&'static self, f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R) -> Option<R>;
+
+    /// Call function with slot, holding current value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if slot is not accessible.
+    fn with<R>(&'static self, f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R) -> R {
+        self.try_with(f)
+            .expect("cannot access context storage during or after destruction")
+    }
 }
 
-#[cfg(test)]
-mod ui_test {
-    use std::time::{Duration, Instant};
+#[cfg(feature = "std")]
+impl<T: 'static> TlsBackend<T> for LocalKey<RefCell<ContextSlot<T>>> {
+    fn try_with<R>(&'static self, f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R) -> Option<R> {
+        LocalKey::try_with(self, f).ok()
+    }
+}
 
-    use super::{FutureExt, TypedContext};
+/// Trait for interaction with typed contexts.
+pub trait TypedContext: Clone + 'static {
+    /// Storage of the current value.
+    type Backend: TlsBackend<Self>;
 
-    #[derive(Clone, Debug, PartialEq)]
-    struct Deadline(Instant);
+    /// Get storage of the current value.
+    fn tls() -> &'static Self::Backend;
 
-    impl Deadline {
-        pub fn after(after: Duration) -> Self {
-            Self(Instant::now() + after)
-        }
+    /// Get counters of attaches and detaches, generated by [`context!`] and derive macro.
+    ///
+    /// Manual implementations share fallback counters, so enabling `metrics` feature doesn't break
+    /// them.
+    #[cfg(feature = "metrics")]
+    #[doc(hidden)]
+    fn counters() -> &'static ContextCounters {
+        static FALLBACK_COUNTERS: ContextCounters = ContextCounters::new();
+        &FALLBACK_COUNTERS
+    }
 
-        pub fn after_secs(after_secs: u64) -> Self {
-            Self::after(Duration::from_secs(after_secs))
-        }
+    /// Get number of times values of this context were attached (including attaches on each poll of
+    /// futures). Attaches, made while storage is not accessible (for example, thread is being
+    /// destroyed), are not counted.
+    ///
+    /// Requires `metrics` feature.
+    #[cfg(feature = "metrics")]
+    fn attach_count() -> u64 {
+        Self::counters().attach.load(Ordering::Relaxed)
     }
 
-    context!(Deadline);
+    /// Get number of times values of this context were detached.
+    ///
+    /// Requires `metrics` feature.
+    #[cfg(feature = "metrics")]
+    fn detach_count() -> u64 {
+        Self::counters().detach.load(Ordering::Relaxed)
+    }
 
-    #[test]
-    fn both_attach() {
-        let x1 = Deadline::after_secs(1);
-        let _x1_guard = x1.clone().attach();
+    /// Get accessor of the current value, combining all ways to read it.
+    ///
+    /// `current_*` methods of this trait are shortcuts for methods of [`ContextAccessor`].
+    fn access() -> ContextAccessor<Self> {
+        ContextAccessor::new()
+    }
 
-        assert_eq!(Deadline::current().unwrap(), x1);
+    /// Get clone of current value of the context.
+    ///
+    /// `None` is returned if no value set.
+    fn current() -> Option<Self> {
+        Self::access().get()
+    }
 
-        let x2 = Deadline::after_secs(2);
-        let x2_guard = unsafe { x2.attach_ref() };
+    /// Call function with reference to current value of the context, without cloning it.
+    ///
+    /// Context must not be modified from inside of `f`.
+    fn with_current_ref<R>(f: impl FnOnce(Option<&Self>) -> R) -> R {
+        Self::access().get_ref(f)
+    }
 
-        assert_eq!(Deadline::current().unwrap(), x2);
+    /// Get shared pointer to current value of the context.
+    ///
+    /// Values, set with [`TypedContext::attach`] or [`TypedContext::attach_arc`], are already stored in
+    /// [`Arc`], so only pointer is cloned, which is cheap for large contexts. Values, set with
+    /// [`TypedContext::attach_ref`], are cloned into new [`Arc`].
+    fn current_arc() -> Option<Arc<Self>> {
+        Self::tls().with(|v| {
+            v.borrow().as_ref().map(|x| match x {
+                ContextValue::Owned(x) | ContextValue::Default(x) => x.clone(),
+                ContextValue::Borrowed(x) => Arc::new(Self::clone(x)),
+            })
+        })
+    }
 
-        drop(x2_guard);
-        drop(x2);
+    /// Get clone of current value of the context and transform it with `f`.
+    ///
+    /// `None` is returned if no value set. If `f` doesn't need ownership of the value, use
+    /// [`TypedContext::with_current_ref`] to avoid cloning.
+    fn current_map<R>(f: impl FnOnce(Self) -> R) -> Option<R> {
+        Self::access().map(f)
+    }
 
-        assert_eq!(Deadline::current().unwrap(), x1);
+    /// Get clone of current value of the context and transform it with fallible `f`.
+    ///
+    /// `None` is returned if no value set or if `f` returned `None`.
+    fn current_flat_map<R>(f: impl FnOnce(Self) -> Option<R>) -> Option<R> {
+        Self::access().flat_map(f)
     }
 
-    #[tokio::test]
-    async fn get_across_spawn() {
-        let x = Deadline::after_secs(1);
+    /// Call function with current value of the context as [`Display`](fmt::Display), without cloning it.
+    ///
+    /// `None` is returned if no value set. Context must not be modified from inside of `f`.
+    fn with_display<R>(f: impl FnOnce(&dyn fmt::Display) -> R) -> Option<R>
+    where
+        Self: fmt::Display,
+    {
+        Self::with_current_ref(|x| x.map(|x| f(x)))
+    }
 
-        assert_eq!(
-            tokio::spawn(async { Deadline::current() }.with(x.clone()))
-                .await
-                .unwrap(),
-            Some(x)
-        )
+    /// Call function with current value of the context as [`Debug`](fmt::Debug), without cloning it.
+    ///
+    /// `None` is returned if no value set. Context must not be modified from inside of `f`.
+    fn with_debug<R>(f: impl FnOnce(&dyn fmt::Debug) -> R) -> Option<R>
+    where
+        Self: fmt::Debug,
+    {
+        Self::with_current_ref(|x| x.map(|x| f(x)))
+    }
+
+    /// Get clone of current value of the context or compute it with `f` if no value set.
+    fn current_or_else(f: impl FnOnce() -> Self) -> Self {
+        Self::access().or_else(f)
+    }
+
+    /// Get clone of current value of the context or default value if no value set.
+    ///
+    /// Default value is not stored, so each call without value set returns fresh default.
+    fn current_or_default() -> Self
+    where
+        Self: Default,
+    {
+        Self::access().or_default()
+    }
+
+    /// Get clone of current value of the context, setting default value first if no value set.
+    ///
+    /// Unlike [`TypedContext::current_or_default`], default value becomes the current context.
+    /// It works like [`TypedContext::attach`] with leaked guard: value is never detached by itself
+    /// and stays until the thread exits, unless it is overwritten (by [`TypedContext::replace`] or
+    /// by [`ContextGuard`] of outer scope, which restores its previous value on drop).
+    fn current_or_insert_default() -> Self
+    where
+        Self: Default,
+    {
+        Self::access().or_insert_default()
+    }
+
+    /// Get number of guards of this context (both [`ContextGuard`] and [`ContextRefGuard`]), which are
+    /// alive on the current thread.
+    ///
+    /// Useful in tests to check, that context is not attached more times than expected. Futures, wrapped
+    /// with [`FutureExt::with`], are counted only while they are polled.
+    fn nesting_depth() -> usize {
+        Self::tls().with(|v| v.borrow().depth())
+    }
+
+    /// Get version of the current value on the current thread.
+    ///
+    /// Version is incremented whenever value changes (it is attached, restored by a guard, modified or
+    /// taken), so it can be used to check, whether value changed since last read without comparing
+    /// values. Futures, wrapped with [`FutureExt::with`], change version on each poll.
+    fn current_version() -> u64 {
+        Self::tls()
+            .try_with(|current| current.borrow().version())
+            .unwrap_or_default()
+    }
+
+    /// Get clone of the current value alongside with its version, if version differs from
+    /// `last_version`.
+    ///
+    /// `None` is returned if version is the same or no value is set.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// use tyco::{context, TypedContext};
+    ///
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// struct Locale(&'static str);
+    ///
+    /// context!(Locale);
+    ///
+    /// let _guard = Locale("en").attach();
+    /// let (locale, version) = Locale::current_if_changed(0).unwrap();
+    /// assert_eq!(locale, Locale("en"));
+    /// assert_eq!(Locale::current_if_changed(version), None);
+    ///
+    /// let _guard = Locale("de").attach();
+    /// assert!(Locale::current_if_changed(version).is_some());
+    /// # }
+    /// ```
+    fn current_if_changed(last_version: u64) -> Option<(Self, u64)> {
+        Self::tls()
+            .try_with(|current| {
+                let current = current.borrow();
+                let version = current.version();
+                if version == last_version {
+                    return None;
+                }
+                current.as_deref().map(|x| (x.clone(), version))
+            })
+            .flatten()
+    }
+
+    /// Check if any value is set, without cloning it.
+    ///
+    /// Default value of the context (see `default` form of [`context!`]) is not considered set.
+    fn is_set() -> bool {
+        Self::access().is_set()
+    }
+
+    /// Clear current context and return its value.
+    ///
+    /// Value is cloned only if it is referenced somewhere else (by [`ContextGuard`] or with
+    /// [`TypedContext::attach_ref`]). Slot stays empty until next attach or until the guard of the
+    /// taken value restores previous value.
+    fn take() -> Option<Self> {
+        Self::tls()
+            .try_with(|current| current.borrow_mut().take())
+            .flatten()
+            .map(ContextValue::into_owned)
+    }
+
+    /// Set value as the current context and return previous value.
+    ///
+    /// Unlike [`TypedContext::attach`], no guard is created and **previous value is not restored**,
+    /// caller is responsible for restoring it. Note that if there is [`ContextGuard`] alive, it will
+    /// overwrite new value with its previous value on drop.
+    fn replace(new_value: Self) -> Option<Self> {
+        Self::tls()
+            .try_with(|current| {
+                current
+                    .borrow_mut()
+                    .replace(Some(ContextValue::Owned(Arc::new(new_value))))
+            })
+            .flatten()
+            .map(ContextValue::into_owned)
+    }
+
+    /// Modify current value of the context in place.
+    ///
+    /// Does nothing if no value set. Value is cloned before modification if it is referenced
+    /// somewhere else (by [`ContextGuard`] or with [`TypedContext::attach_ref`]), so modification is
+    /// not visible through [`ContextGuard::value`] and is discarded when the guard restores previous
+    /// value (for futures wrapped with [`FutureExt::with`] it means the end of current poll).
+    ///
+    /// Clone happens only on first modification, subsequent modifications and modifications of values,
+    /// set with [`TypedContext::replace`], don't clone.
+    ///
+    /// Context must not be accessed from inside of `f`.
+    fn update(f: impl FnOnce(&mut Self)) {
+        let _ = Self::tls().try_with(|current| {
+            if let Some(value) = current.borrow_mut().as_mut() {
+                f(value.to_mut())
+            }
+        });
+    }
+
+    /// Modify current value of the context in place, cloning it only when it can't be mutated directly.
+    ///
+    /// Same as [`TypedContext::update`]: value, set with [`TypedContext::attach_ref`], is cloned on
+    /// first modification. Value, set with [`TypedContext::attach`], is shared with its guard (to be
+    /// available with [`ContextGuard::value`]), so it is cloned as well; use [`ContextGuard::modify`]
+    /// to modify it without cloning.
+    fn modify(f: impl FnOnce(&mut Self)) {
+        Self::update(f)
+    }
+
+    /// Set value as the current context.
+    ///
+    /// It will live as long as returned guard is alive. Previous value is stored
+    /// inside guard and will be restored on drop.
+    fn attach(self) -> ContextGuard<Self> {
+        ContextGuard::new(Arc::new(self))
+    }
+
+    /// Set value as the current context only if current value is equal to `expected`.
+    ///
+    /// Comparison and attaching are performed under single access to the slot, so nothing can change
+    /// the value in between (comparison must not access the context itself). Default value of the
+    /// context is compared like any other value. If no value is set or it is not equal to `expected`,
+    /// new value is returned back.
+    fn compare_and_swap(expected: &Self, new: Self) -> Result<ContextGuard<Self>, Self>
+    where
+        Self: PartialEq,
+    {
+        ContextGuard::new_if_current(Arc::new(new), expected).map_err(Arc::unwrap_or_clone)
+    }
+
+    /// Register observer, which is called when values of this context are attached and detached.
+    ///
+    /// Only one observer can be registered for each context type, observer is returned back if
+    /// there is one already.
+    #[cfg(feature = "std")]
+    fn set_observer(
+        observer: Box<dyn ContextObserver<Self>>,
+    ) -> Result<(), Box<dyn ContextObserver<Self>>> {
+        observer::set_observer(observer)
+    }
+
+    /// Set value as the current context only if no value is set.
+    ///
+    /// Returns `None` and leaves current value untouched if it is set. Default value of the context
+    /// (see [`TypedContext::is_set`]) is replaced.
+    fn attach_with_default(value: Self) -> Option<ContextGuard<Self>> {
+        (!Self::is_set()).then(|| value.attach())
+    }
+
+    /// Set shared value as the current context.
+    ///
+    /// Same as [`TypedContext::attach`], but value is not moved into new allocation, so value, got with
+    /// [`TypedContext::current_arc`], can be attached again without cloning.
+    fn attach_arc(value: Arc<Self>) -> ContextGuard<Self> {
+        ContextGuard::new(value)
+    }
+
+    /// Set value as the current context for the duration of the closure call.
+    ///
+    /// Previous value is restored after closure returns, even if it panics.
+    fn scoped<R>(self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.attach();
+        f()
+    }
+
+    /// Set value as the current context for the duration of the closure call.
+    ///
+    /// Same as [`TypedContext::scoped`], but called on the type: `T::run_with(value, || ...)`. If `f`
+    /// panics, guard is dropped during unwinding, so previous value is restored and panic is
+    /// propagated to the caller as is.
+    fn run_with<R>(value: Self, f: impl FnOnce() -> R) -> R {
+        value.scoped(f)
+    }
+
+    /// Set value as the current context for a future.
+    ///
+    /// Same as [`FutureExt::with`]: value is set only while the future is polled.
+    fn scoped_async<F: Future>(self, f: F) -> WithContext<F, Self> {
+        f.with(self)
+    }
+
+    /// Set value as the current context for a future.
+    ///
+    /// Alias for [`TypedContext::scoped_async`], which doesn't require [`FutureExt`] to be imported.
+    /// Prefer it over holding [`ContextGuard`] across `.await`: guard would keep value set outside of
+    /// the polls of the future and make it `!Send`.
+    fn scope<F: Future>(self, f: F) -> WithContext<F, Self> {
+        self.scoped_async(f)
+    }
+
+    /// Set reference to a value as current context.
+    ///
+    /// This function is mainly used for [`FutureExt`] implementation and should
+    /// not be used by user (or used with great caution !!!).
+    ///
+    /// # Safety
+    ///
+    /// Reference is stored in TLS as `&'static Self` until the guard is dropped, so caller must ensure
+    /// that:
+    /// - the guard is not leaked (for example, with [`core::mem::forget`]), since lifetime of the guard
+    ///   is the only thing, which keeps reference in TLS valid;
+    /// - guards of the context are dropped in reverse order of creation, otherwise some guard may
+    ///   restore the reference after the guard, which owns it, is dropped.
+    ///
+    /// Incorrect usage might lead to segfault.
+    ///
+    /// If storage is shared between threads (see [`TlsBackend::SHARED`]), value is cloned instead.
+    unsafe fn attach_ref(&self) -> ContextRefGuard<'_, Self> {
+        let value = if Self::Backend::SHARED {
+            ContextValue::Owned(Arc::new(self.clone()))
+        } else {
+            ContextValue::Borrowed(unsafe { &*(self as *const Self) })
+        };
+        let previous_value = Self::tls().try_with(|current| current.borrow_mut().attach(value));
+        notify_attach(self, previous_value.as_ref());
+
+        ContextRefGuard {
+            value: self,
+            previous_value,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Formatter of current value of the context, which can be used in formatting macros directly.
+///
+/// Value is formatted at the moment of formatting (not when wrapper is created) without cloning it,
+/// nothing is written if no value set.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use std::fmt;
+///
+/// use tyco::{context, ContextDisplay, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(String);
+///
+/// impl fmt::Display for TraceId {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         f.write_str(&self.0)
+///     }
+/// }
+///
+/// context!(TraceId);
+///
+/// let _guard = TraceId("abc".into()).attach();
+/// assert_eq!(format!("[{}]", ContextDisplay::<TraceId>::new()), "[abc]");
+/// # }
+/// ```
+pub struct ContextDisplay<T>(PhantomData<fn() -> T>);
+
+impl<T> ContextDisplay<T> {
+    /// Create formatter of the context.
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for ContextDisplay<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: TypedContext + fmt::Display> fmt::Display for ContextDisplay<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        T::with_current_ref(|x| x.map_or(Ok(()), |x| x.fmt(f)))
+    }
+}
+
+/// Accessor of the current value of the context, created with [`TypedContext::access`].
+///
+/// Combines all ways to read current value in one place. Value is read when method is called, not
+/// when accessor is created.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context, TypedContext};
+///
+/// #[derive(Clone, Debug, Default, PartialEq)]
+/// struct Retries(u32);
+///
+/// context!(Retries);
+///
+/// assert_eq!(Retries::access().get(), None);
+/// assert_eq!(Retries::access().or_default(), Retries(0));
+///
+/// let _guard = Retries(3).attach();
+/// assert_eq!(Retries::access().map(|x| x.0 * 2), Some(6));
+/// assert_eq!(Retries::access().get_ref(|x| x.is_some()), true);
+/// # }
+/// ```
+pub struct ContextAccessor<T>(PhantomData<fn() -> T>);
+
+impl<T> ContextAccessor<T> {
+    /// Create accessor of the context.
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for ContextAccessor<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for ContextAccessor<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ContextAccessor<T> {}
+
+impl<T> fmt::Debug for ContextAccessor<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ContextAccessor")
+    }
+}
+
+impl<T: TypedContext> ContextAccessor<T> {
+    /// Get clone of current value. `None` is returned if no value set.
+    pub fn get(self) -> Option<T> {
+        T::tls().with(|v| v.borrow().as_deref().cloned())
+    }
+
+    /// Call function with reference to current value, without cloning it.
+    ///
+    /// Context must not be modified from inside of `f`.
+    pub fn get_ref<R>(self, f: impl FnOnce(Option<&T>) -> R) -> R {
+        T::tls().with(|v| f(v.borrow().as_deref()))
+    }
+
+    /// Get clone of current value and transform it with `f`.
+    pub fn map<R>(self, f: impl FnOnce(T) -> R) -> Option<R> {
+        self.get().map(f)
+    }
+
+    /// Get clone of current value and transform it with fallible `f`.
+    pub fn flat_map<R>(self, f: impl FnOnce(T) -> Option<R>) -> Option<R> {
+        self.get().and_then(f)
+    }
+
+    /// Get clone of current value or compute it with `f` if no value set.
+    pub fn or_else(self, f: impl FnOnce() -> T) -> T {
+        self.get().unwrap_or_else(f)
+    }
+
+    /// Get clone of current value or default value if no value set.
+    ///
+    /// Default value is not stored, see [`TypedContext::current_or_default`].
+    pub fn or_default(self) -> T
+    where
+        T: Default,
+    {
+        self.get().unwrap_or_default()
+    }
+
+    /// Get clone of current value, setting default value first if no value set.
+    ///
+    /// See [`TypedContext::current_or_insert_default`].
+    pub fn or_insert_default(self) -> T
+    where
+        T: Default,
+    {
+        T::tls().with(|current| {
+            let mut current = current.borrow_mut();
+            let value = current.get_or_insert_with(|| ContextValue::Owned(Arc::new(T::default())));
+            T::clone(value)
+        })
+    }
+
+    /// Check if any value is set, without cloning it.
+    ///
+    /// Default value of the context is not considered set.
+    pub fn is_set(self) -> bool {
+        T::tls().with(|v| v.borrow().as_ref().is_some_and(|x| !x.is_default()))
+    }
+}
+
+/// Report attach of the value to metrics, history and observer of the context.
+///
+/// Called by all guards after value is set, `previous_value` is `None` if storage was not accessible
+/// (nothing is reported in this case).
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+fn notify_attach<T: TypedContext>(value: &T, previous_value: Option<&Option<ContextValue<T>>>) {
+    let Some(previous_value) = previous_value else {
+        return;
+    };
+
+    #[cfg(feature = "metrics")]
+    T::counters().attach.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "debug-history")]
+    history::record::<T>(history::ContextAction::Attach);
+
+    #[cfg(feature = "std")]
+    if let Some(observer) = observer::observer::<T>() {
+        observer.on_attach(value, previous_value.as_deref());
+    }
+}
+
+/// Restore previous value of the context, replacing attached `value`, and report detach to metrics,
+/// history and observer of the context.
+///
+/// Used by all guards. Returns value, which was current before restoring (`None` if storage is not
+/// accessible).
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+fn restore_previous<T: TypedContext>(
+    value: &T,
+    previous_value: Option<ContextValue<T>>,
+) -> Option<Option<ContextValue<T>>> {
+    #[cfg(feature = "std")]
+    if let Some(observer) = observer::observer::<T>() {
+        observer.on_detach(value, previous_value.as_deref());
+    }
+
+    let current = T::tls().try_with(|current| current.borrow_mut().detach(previous_value));
+    if current.is_some() {
+        #[cfg(feature = "metrics")]
+        T::counters().detach.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "debug-history")]
+        history::record::<T>(history::ContextAction::Detach);
+    }
+
+    current
+}
+
+/// Guard, created with [`TypedContext::attach`], keeping value as current context.
+///
+/// On drop it will restore previous value.
+///
+/// Guard is never `Send`, even if `T` is: it restores previous value in TLS of the thread it is
+/// dropped on, so dropping it on another thread would overwrite context of that thread with value of
+/// the original one. To pass context to another thread, send the value itself (see
+/// [`spawn_with_context`] and [`context_send!`]).
+///
+/// Guard should not be held across `.await`, use [`with_temporary_async`] or [`FutureExt`] instead.
+///
+/// Guard must be held in a binding, otherwise context is restored immediately:
+///
+/// ```compile_fail
+/// #![deny(unused_must_use)]
+///
+/// #[derive(Clone)]
+/// struct TraceId(String);
+///
+/// tyco::context!(TraceId);
+///
+/// tyco::TypedContext::attach(TraceId("trace".into())); // guard is dropped right away
+/// ```
+#[must_use = "ContextGuard must be held in a binding; dropping it immediately unsets the context"]
+pub struct ContextGuard<T: TypedContext> {
+    value: Arc<T>,
+    previous_value: Option<Option<ContextValue<T>>>,
+    _marker: PhantomData<*const ()>,
+}
+
+impl<T: TypedContext> ContextGuard<T> {
+    fn new(value: Arc<T>) -> Self {
+        let previous_value = T::tls().try_with(|current| {
+            current
+                .borrow_mut()
+                .attach(ContextValue::Owned(value.clone()))
+        });
+
+        Self::attached(value, previous_value)
+    }
+
+    /// Attach value only if current value matches `expected`, checking and attaching under single
+    /// borrow of the slot.
+    fn new_if_current(value: Arc<T>, expected: &T) -> Result<Self, Arc<T>>
+    where
+        T: PartialEq,
+    {
+        let previous_value = T::tls()
+            .try_with(|current| {
+                let mut current = current.borrow_mut();
+                (current.as_deref() == Some(expected))
+                    .then(|| current.attach(ContextValue::Owned(value.clone())))
+            })
+            .flatten();
+
+        match previous_value {
+            Some(previous_value) => Ok(Self::attached(value, Some(previous_value))),
+            None => Err(value),
+        }
+    }
+
+    fn attached(value: Arc<T>, previous_value: Option<Option<ContextValue<T>>>) -> Self {
+        // Previous value may be borrowed by a wrapper (like `WithContext`), which can be dropped before
+        // this guard, so guard keeps its own copy.
+        let previous_value = previous_value.map(|x| x.map(ContextValue::into_shared));
+        notify_attach(&*value, previous_value.as_ref());
+
+        Self {
+            value,
+            previous_value,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Attach value and return guard alongside with shared handle to the attached value.
+    ///
+    /// Value is not cloned: TLS, guard and returned [`Arc`] share the same allocation, so the value can
+    /// be used after attaching without calling [`TypedContext::current`]. Handle is not tied to the
+    /// lifetime of the guard (reference to the value inside of the guard is available with
+    /// [`ContextGuard::value`]).
+    pub fn from_value(value: T) -> (Self, Arc<T>) {
+        let value = Arc::new(value);
+        (Self::new(value.clone()), value)
+    }
+
+    /// Get reference to the attached value.
+    ///
+    /// `None` is returned if value was not attached (TLS is already destroyed).
+    pub fn value(&self) -> Option<&T> {
+        self.previous_value.as_ref().map(|_| self.value.as_ref())
+    }
+
+    /// Modify attached value in place.
+    ///
+    /// Unlike [`TypedContext::modify`], value is not cloned, unless it is referenced somewhere else
+    /// (for example, by [`TypedContext::current_arc`] or [`ContextGuard::from_value`] handles).
+    /// Modification is visible both through the context and [`ContextGuard::value`]. Nothing is done
+    /// and `false` is returned if attached value is not the current value of the context (it was
+    /// replaced or another value was attached on top of it).
+    ///
+    /// Context must not be accessed from inside of `f`.
+    pub fn modify(&mut self, f: impl FnOnce(&mut T)) -> bool {
+        T::tls()
+            .try_with(|current| {
+                let mut current = current.borrow_mut();
+                if !matches!(&**current, Some(ContextValue::Owned(x)) if Arc::ptr_eq(x, &self.value)) {
+                    return false;
+                }
+
+                // Release reference, held by the slot, so value can be mutated without cloning.
+                current.replace(None);
+                f(Arc::make_mut(&mut self.value));
+                current.replace(Some(ContextValue::Owned(self.value.clone())));
+                true
+            })
+            .unwrap_or(false)
+    }
+
+    /// Restore previous value and return the attached one.
+    ///
+    /// Value is cloned only if it is still referenced somewhere else (for example, if guards were
+    /// dropped not in reverse order of creation).
+    pub fn detach(self) -> T {
+        let value = self.value.clone();
+        drop(self);
+        Arc::unwrap_or_clone(value)
+    }
+
+    /// Replace attached value with value of another context, computed from it.
+    ///
+    /// Value of `U` is attached before previous value of `T` is restored, so there is no moment, when
+    /// neither of contexts is set. Since attached value is still shared with TLS at the moment `f` is
+    /// called, it receives a clone of the value.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// use tyco::{context, TypedContext};
+    ///
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// struct ParentSpanId(u64);
+    ///
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// struct ChildSpanId(u64);
+    ///
+    /// context!(ParentSpanId);
+    /// context!(ChildSpanId);
+    ///
+    /// let guard = ParentSpanId(1).attach().map(|x| ChildSpanId(x.0 + 1));
+    ///
+    /// assert_eq!(ParentSpanId::current(), None);
+    /// assert_eq!(ChildSpanId::current(), Some(ChildSpanId(2)));
+    /// # }
+    /// ```
+    pub fn map<U: TypedContext>(self, f: impl FnOnce(T) -> U) -> ContextGuard<U> {
+        let guard = f(T::clone(&self.value)).attach();
+        drop(self);
+        guard
+    }
+
+    /// Restore previous value and return the value, which was current before restoring.
+    ///
+    /// Unlike [`ContextGuard::detach`], modifications of the current value (made with
+    /// [`TypedContext::update`] or [`TypedContext::replace`]) are preserved. Value is cloned only if it
+    /// is referenced somewhere else (for example, it was set with [`TypedContext::attach_ref`]). If
+    /// context is empty at that moment, attached value is returned.
+    pub fn into_value(mut self) -> T {
+        let current = self
+            .previous_value
+            .take()
+            .and_then(|previous_value| restore_previous(&*self.value, previous_value)?);
+        let value = self.value.clone();
+        drop(self);
+
+        match current {
+            Some(current) => {
+                drop(value);
+                current.into_owned()
+            }
+            None => Arc::unwrap_or_clone(value),
+        }
+    }
+
+    /// Leak the guard, so attached value is never restored.
+    ///
+    /// Context persists forever on this thread (until it is replaced or thread is destroyed), which is
+    /// useful for values set once during startup, like name of the service. Note that guards, attached
+    /// before, still restore their previous values on drop, so it should be called on the outermost
+    /// guard.
+    pub fn forget(self) {
+        core::mem::forget(self)
+    }
+
+    /// Restore previous value of the context after timeout.
+    ///
+    /// Returned guard is a future, which completes when timeout expires, see [`TimeoutGuard`].
+    ///
+    /// Requires `tokio` feature.
+    #[cfg(feature = "tokio")]
+    pub fn timeout(self, duration: core::time::Duration) -> TimeoutGuard<T> {
+        TimeoutGuard::new(self, duration)
+    }
+
+    /// Attach value of another context and return guard, holding both guards.
+    ///
+    /// Guards are dropped in reverse order of attaching, so previous values are always restored
+    /// correctly. Calls can be chained: `a.attach().and_also(b).and_also(c)`.
+    pub fn and_also<U: TypedContext>(self, other: U) -> CompositeGuard<Self, U> {
+        CompositeGuard::new(self, other)
+    }
+
+    /// Attach value of the guard once more, while returned guard is alive.
+    ///
+    /// Value is not cloned (TLS gets another reference to the [`Arc`], shared with this guard). Returned
+    /// guard restores value, which was current when it was created (normally value of this guard), so
+    /// helpers can be called with guard, which can't outlive this one, while previous value of this
+    /// guard is preserved.
+    pub fn reborrow(&mut self) -> ContextRefGuard<'_, T> {
+        let previous_value = T::tls().try_with(|current| {
+            current
+                .borrow_mut()
+                .attach(ContextValue::Owned(self.value.clone()))
+                .map(ContextValue::into_shared)
+        });
+        notify_attach(&*self.value, previous_value.as_ref());
+
+        ContextRefGuard {
+            value: &self.value,
+            previous_value,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Call function while the guard is alive and return its result alongside with the guard.
+    ///
+    /// Unlike [`TypedContext::run_with`], value stays attached after the call, so the guard can be used
+    /// further. If `f` panics, the guard is dropped during unwinding and previous value is restored.
+    pub fn with_scope<R>(self, f: impl FnOnce() -> R) -> (R, Self) {
+        (f(), self)
+    }
+
+    /// Temporarily clear current context, while returned guard is alive.
+    ///
+    /// Useful for calling code, which must not see the context, without dropping the guard. Value,
+    /// which was current before suspension, is restored when returned guard is dropped.
+    pub fn suspend(&mut self) -> SuspendGuard<'_, T> {
+        SuspendGuard {
+            _clear: ClearGuard::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Set new value as current context and return value, which was current before.
+    ///
+    /// On drop guard still restores value, which was current before [`TypedContext::attach`].
+    pub fn swap(&mut self, new_value: T) -> T {
+        let new_value = Arc::new(new_value);
+        let old_value = core::mem::replace(&mut self.value, new_value.clone());
+
+        if self.previous_value.is_none() {
+            return Arc::unwrap_or_clone(old_value);
+        }
+
+        match T::tls()
+            .try_with(|current| {
+                current
+                    .borrow_mut()
+                    .replace(Some(ContextValue::Owned(new_value)))
+            })
+            .flatten()
+        {
+            Some(current) => {
+                drop(old_value);
+                current.into_owned()
+            }
+            None => Arc::unwrap_or_clone(old_value),
+        }
+    }
+}
+
+/// Cloning guard attaches its value again, creating independent guard.
+///
+/// New guard will restore value, which is current at the time of cloning, so it should be dropped
+/// before the original one.
+impl<T: TypedContext> Clone for ContextGuard<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone())
+    }
+}
+
+/// Reference to the attached value, which is stored in the guard.
+///
+/// Unlike [`ContextGuard::value`] value is returned even if it was not attached, and it is not
+/// affected by changes of the current context (made with [`TypedContext::replace`]).
+impl<T: TypedContext> AsRef<T> for ContextGuard<T> {
+    fn as_ref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// Guard is equal to a value, if attached value is equal to it.
+///
+/// Attached value is compared (without cloning), not the current value of the context.
+impl<T: TypedContext + PartialEq> PartialEq<T> for ContextGuard<T> {
+    fn eq(&self, other: &T) -> bool {
+        *self.value == *other
+    }
+}
+
+/// Guards are equal, if their attached values are equal.
+impl<T: TypedContext + PartialEq> PartialEq for ContextGuard<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: TypedContext> Drop for ContextGuard<T> {
+    fn drop(&mut self) {
+        if let Some(previous_value) = self.previous_value.take() {
+            let _ = restore_previous(&*self.value, previous_value);
+        }
+    }
+}
+
+/// Guard, created with [`ContextGuard::and_also`], keeping values of several contexts.
+///
+/// On drop it will restore previous value of the last attached context `U` first, and then drop guard
+/// of the other contexts `G`.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(String);
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct UserId(u64);
+///
+/// context!(TraceId, TRACE_ID_CONTEXT);
+/// context!(UserId, USER_ID_CONTEXT);
+///
+/// let guard = TraceId("trace".into()).attach().and_also(UserId(1));
+/// assert_eq!(UserId::current(), Some(UserId(1)));
+///
+/// drop(guard);
+/// assert_eq!(TraceId::current(), None);
+/// assert_eq!(UserId::current(), None);
+/// # }
+/// ```
+#[must_use = "CompositeGuard must be held in a binding; dropping it immediately unsets the contexts"]
+pub struct CompositeGuard<G, U: TypedContext> {
+    // Fields are dropped in order of declaration.
+    last: ContextGuard<U>,
+    rest: G,
+}
+
+impl<G, U: TypedContext> CompositeGuard<G, U> {
+    fn new(rest: G, value: U) -> Self {
+        Self {
+            last: value.attach(),
+            rest,
+        }
+    }
+
+    /// Attach value of another context.
+    pub fn and_also<V: TypedContext>(self, other: V) -> CompositeGuard<Self, V> {
+        CompositeGuard::new(self, other)
+    }
+
+    /// Split into guard of the other contexts and guard of the last attached context.
+    pub fn into_inner(self) -> (G, ContextGuard<U>) {
+        (self.rest, self.last)
+    }
+}
+
+/// Guard, created with [`ContextGuard::suspend`], keeping context cleared.
+///
+/// On drop it will restore value, which was current before suspension.
+#[must_use = "SuspendGuard must be held in a binding; dropping it immediately restores the context"]
+pub struct SuspendGuard<'a, T: TypedContext> {
+    _clear: ClearGuard<T>,
+    _marker: PhantomData<&'a mut ContextGuard<T>>,
+}
+
+/// Guard, created with [`TypedContext::attach_ref`], keeping value as current context.
+///
+/// On drop it will restore previous value.
+#[must_use = "ContextRefGuard must be held in a binding; dropping it immediately unsets the context"]
+pub struct ContextRefGuard<'a, T: TypedContext> {
+    value: &'a T,
+    previous_value: Option<Option<ContextValue<T>>>,
+    _marker: PhantomData<*const ()>,
+}
+
+impl<T: TypedContext> ContextRefGuard<'_, T> {
+    /// Get reference to the attached value.
+    ///
+    /// `None` is returned if value was not attached (TLS is already destroyed).
+    pub fn value(&self) -> Option<&T> {
+        self.previous_value.as_ref().map(|_| self.value)
+    }
+
+    /// Convert into owned guard, which doesn't borrow the value.
+    ///
+    /// Current value of the context is cloned and replaces borrowed one in place, so context stays
+    /// set during conversion. Returned guard restores the same value as this guard would, cloning it
+    /// if it is borrowed too. Attach is not reported again, drop of returned guard reports detach of
+    /// the value, attached by this guard.
+    pub fn upgrade(mut self) -> ContextGuard<T> {
+        // Returned guard can outlive wrapper (like `WithContext`), which borrowed previous value.
+        let previous_value = self
+            .previous_value
+            .take()
+            .map(|x| x.map(ContextValue::into_shared));
+        let value = previous_value
+            .as_ref()
+            .and_then(|_| {
+                T::tls().try_with(|current| {
+                    let mut current = current.borrow_mut();
+                    let value = Arc::new(current.as_deref().unwrap_or(self.value).clone());
+                    current.replace(Some(ContextValue::Owned(value.clone())));
+                    value
+                })
+            })
+            .unwrap_or_else(|| Arc::new(self.value.clone()));
+
+        ContextGuard {
+            value,
+            previous_value,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: TypedContext> Drop for ContextRefGuard<'a, T> {
+    fn drop(&mut self) {
+        if let Some(previous_value) = self.previous_value.take() {
+            let _ = restore_previous(self.value, previous_value);
+        }
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, responsible for managing its context.
+    ///
+    /// Context is set only while future is polled, code, executed by waker of the future (when it is
+    /// woken from I/O driver or another thread), doesn't see it. Use
+    /// [`FutureExt::with_context_waker`] if it is needed.
+    ///
+    /// Value is dropped as soon as future completes. Futures must not be polled after they returned
+    /// [`Poll::Ready`], but if it happens, inner future is polled without context (most futures,
+    /// including `async` blocks, panic in this case).
+    #[derive(Clone)]
+    pub struct WithContext<F, T> {
+        #[pin]
+        inner: F,
+        value: Option<T>,
+    }
+}
+
+impl<F: Future, T: TypedContext> Future for WithContext<F, T> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let res = if let Some(ref x) = this.value {
+            let _guard = unsafe { x.attach_ref() };
+            this.inner.poll(cx)
+        } else {
+            this.inner.poll(cx)
+        };
+
+        if res.is_ready() {
+            *this.value = None;
+        }
+
+        res
+    }
+}
+
+impl<F, T> WithContext<F, T> {
+    /// Get reference to the context value.
+    ///
+    /// `None` is returned after future completed.
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    /// Unwrap inner future and context value.
+    ///
+    /// Wrapper is moved, so it must not be pinned yet (or future must be [`Unpin`]). For pinned boxed
+    /// futures use [`WithContext::into_inner_boxed`].
+    pub fn into_inner(self) -> (F, Option<T>) {
+        (self.inner, self.value)
+    }
+
+    /// Unwrap inner future and context value from pinned box.
+    pub fn into_inner_boxed(self: Pin<Box<Self>>) -> (F, Option<T>)
+    where
+        F: Unpin,
+    {
+        Pin::into_inner(self).into_inner()
+    }
+}
+
+impl<F, T: fmt::Debug> fmt::Debug for WithContext<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithContext")
+            .field("inner", &format_args!("<Future>"))
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<F: FusedFuture, T: TypedContext> FusedFuture for WithContext<F, T> {
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, computing its context on first poll.
+    ///
+    /// Value is dropped as soon as future completes.
+    #[derive(Clone)]
+    pub struct WithContextFn<Fut, T, F> {
+        #[pin]
+        inner: Fut,
+        init: Option<F>,
+        value: Option<T>,
+    }
+}
+
+impl<Fut, T, F, R> Future for WithContextFn<Fut, T, F>
+where
+    Fut: Future,
+    T: TypedContext,
+    F: FnOnce() -> R,
+    R: Into<Option<T>>,
+{
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Some(init) = this.init.take() {
+            *this.value = init().into();
+        }
+
+        let res = {
+            let _guard = this.value.as_ref().map(|x| unsafe { x.attach_ref() });
+            this.inner.poll(cx)
+        };
+
+        if res.is_ready() {
+            *this.value = None;
+        }
+
+        res
+    }
+}
+
+impl<Fut, T: fmt::Debug, F> fmt::Debug for WithContextFn<Fut, T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithContextFn")
+            .field("inner", &format_args!("<Future>"))
+            .field("initialized", &self.init.is_none())
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, calling callback with current value of the context, when future
+    /// completes.
+    ///
+    /// Created with [`FutureExt::with_on_ready`].
+    pub struct WithOnReady<Fut, T, F> {
+        #[pin]
+        inner: Fut,
+        on_ready: Option<F>,
+        _marker: PhantomData<fn() -> T>,
+    }
+}
+
+impl<Fut, T, F> Future for WithOnReady<Fut, T, F>
+where
+    Fut: Future,
+    T: TypedContext,
+    F: FnOnce(Option<T>),
+{
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let res = this.inner.poll(cx);
+        if res.is_ready() {
+            if let Some(on_ready) = this.on_ready.take() {
+                on_ready(T::current());
+            }
+        }
+
+        res
+    }
+}
+
+impl<Fut, T, F> fmt::Debug for WithOnReady<Fut, T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithOnReady")
+            .field("inner", &format_args!("<Future>"))
+            .field("completed", &self.on_ready.is_none())
+            .finish()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, managing two contexts at once.
+    ///
+    /// Same as nested [`WithContext`], but attaches both values in a single `poll`. Values are dropped
+    /// as soon as future completes.
+    #[derive(Clone)]
+    pub struct WithContextMulti<F, T1, T2> {
+        #[pin]
+        inner: F,
+        value1: Option<T1>,
+        value2: Option<T2>,
+    }
+}
+
+impl<F: Future, T1: TypedContext, T2: TypedContext> Future for WithContextMulti<F, T1, T2> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let res = {
+            let _guard1 = this.value1.as_ref().map(|x| unsafe { x.attach_ref() });
+            let _guard2 = this.value2.as_ref().map(|x| unsafe { x.attach_ref() });
+            this.inner.poll(cx)
+        };
+
+        if res.is_ready() {
+            *this.value1 = None;
+            *this.value2 = None;
+        }
+
+        res
+    }
+}
+
+impl<F, T1: fmt::Debug, T2: fmt::Debug> fmt::Debug for WithContextMulti<F, T1, T2> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithContextMulti")
+            .field("inner", &format_args!("<Future>"))
+            .field("value1", &self.value1)
+            .field("value2", &self.value2)
+            .finish()
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<F: FusedFuture, T1: TypedContext, T2: TypedContext> FusedFuture
+    for WithContextMulti<F, T1, T2>
+{
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, setting context while future is polled only if no value is set.
+    ///
+    /// Value is dropped as soon as future completes.
+    #[derive(Clone)]
+    pub struct WithDefault<F, T> {
+        #[pin]
+        inner: F,
+        value: Option<T>,
+    }
+}
+
+impl<F: Future, T: TypedContext> Future for WithDefault<F, T> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let res = {
+            let _guard = this
+                .value
+                .as_ref()
+                .filter(|_| !T::is_set())
+                .map(|x| unsafe { x.attach_ref() });
+            this.inner.poll(cx)
+        };
+
+        if res.is_ready() {
+            *this.value = None;
+        }
+
+        res
+    }
+}
+
+impl<F, T: fmt::Debug> fmt::Debug for WithDefault<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithDefault")
+            .field("inner", &format_args!("<Future>"))
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+/// Guard, clearing current context and restoring previous value on drop.
+struct ClearGuard<T: TypedContext> {
+    previous_value: Option<Option<ContextValue<T>>>,
+}
+
+impl<T: TypedContext> ClearGuard<T> {
+    fn new() -> Self {
+        Self {
+            previous_value: T::tls().try_with(|current| current.borrow_mut().take()),
+        }
+    }
+}
+
+impl<T: TypedContext> Drop for ClearGuard<T> {
+    fn drop(&mut self) {
+        if let Some(previous_value) = self.previous_value.take() {
+            let _ = T::tls().try_with(|current| current.borrow_mut().replace(previous_value));
+        }
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, clearing context while future is polled.
+    pub struct WithoutContext<F, T> {
+        #[pin]
+        inner: F,
+        _marker: PhantomData<fn() -> T>,
+    }
+}
+
+impl<F: Future, T: TypedContext> Future for WithoutContext<F, T> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let _guard = ClearGuard::<T>::new();
+        self.project().inner.poll(cx)
+    }
+}
+
+impl<F: Clone, T> Clone for WithoutContext<F, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F, T> fmt::Debug for WithoutContext<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithoutContext")
+            .field("inner", &format_args!("<Future>"))
+            .field("context", &core::any::type_name::<T>())
+            .finish()
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<F: FusedFuture, T: TypedContext> FusedFuture for WithoutContext<F, T> {
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, which keeps [`ContextGuard`] alive as long as the future exists.
+    pub struct WithOwnedGuard<F, T: TypedContext> {
+        #[pin]
+        inner: F,
+        guard: ContextGuard<T>,
+    }
+}
+
+impl<F: Future, T: TypedContext> Future for WithOwnedGuard<F, T> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Context is already set by the guard, nothing to attach here.
+        self.project().inner.poll(cx)
+    }
+}
+
+/// Extension trait allowing to attach context to futures.
+pub trait FutureExt: Sized {
+    /// Set value as context for future.
+    fn with<T>(self, value: T) -> WithContext<Self, T> {
+        WithContext {
+            inner: self,
+            value: Some(value),
+        }
+    }
+
+    /// Set optional value as context for future.
+    ///
+    /// Primarily used with return value of [`TypedContext::current`].
+    fn with_opt<T>(self, value: Option<T>) -> WithContext<Self, T> {
+        WithContext { inner: self, value }
+    }
+
+    /// Take current context and set is as context for a future.
+    ///
+    /// Basically it is `self.with_opt(T::current())`.
+    fn with_current<T: TypedContext>(self) -> WithContext<Self, T> {
+        self.with_opt(T::current())
+    }
+
+    /// Set two values as context for future.
+    ///
+    /// Equivalent to `self.with(v2).with(v1)`, but without nesting wrappers.
+    fn with2<T1, T2>(self, v1: T1, v2: T2) -> WithContextMulti<Self, T1, T2> {
+        WithContextMulti {
+            inner: self,
+            value1: Some(v1),
+            value2: Some(v2),
+        }
+    }
+
+    /// Take two current contexts and set them as context for a future.
+    fn with_current2<T1: TypedContext, T2: TypedContext>(self) -> WithContextMulti<Self, T1, T2> {
+        WithContextMulti {
+            inner: self,
+            value1: T1::current(),
+            value2: T2::current(),
+        }
+    }
+
+    /// Take current value of context `A` or, if it is missing, of context `B` and set it as context
+    /// for a future.
+    ///
+    /// Only one of contexts is set while future is polled, the other one is left untouched.
+    fn with_current_or<A: TypedContext, B: TypedContext>(self) -> OrContext<Self, A, B> {
+        OrContext::new(self)
+    }
+
+    /// Set value as context for future, if no value is set at the time of poll.
+    ///
+    /// Useful for middlewares, providing fallback values, which must not overwrite values, set by
+    /// upstream.
+    fn with_default<T: TypedContext>(self, value: T) -> WithDefault<Self, T> {
+        WithDefault {
+            inner: self,
+            value: Some(value),
+        }
+    }
+
+    /// Set lazily computed value as context for future.
+    ///
+    /// Function is called on first poll of the future, so value is not computed if future is never
+    /// polled.
+    fn with_fn<T, F: FnOnce() -> T>(self, f: F) -> WithContextFn<Self, T, F> {
+        WithContextFn {
+            inner: self,
+            init: Some(f),
+            value: None,
+        }
+    }
+
+    /// Take current value of context `T`, transform it into context `U` and set it as context for
+    /// future.
+    ///
+    /// Value is transformed immediately, so it is basically `self.with_opt(T::current().map(f))`.
+    /// Useful for deriving contexts of spawned tasks from context of the parent (like child spans).
+    fn map_context<T: TypedContext, U: TypedContext>(
+        self,
+        f: impl FnOnce(T) -> U,
+    ) -> WithContext<Self, U> {
+        self.with_opt(T::current().map(f))
+    }
+
+    /// Same as [`FutureExt::map_context`], but value is taken and transformed on first poll of the
+    /// future.
+    ///
+    /// Context `T` is read where future is polled, not where it is created (see
+    /// [`FutureExt::with_fn`]).
+    fn map_context_with_current<T: TypedContext, U: TypedContext, F: FnOnce(T) -> U>(
+        self,
+        f: F,
+    ) -> WithContextFn<Self, U, impl FnOnce() -> Option<U>> {
+        WithContextFn {
+            inner: self,
+            init: Some(move || T::current().map(f)),
+            value: None,
+        }
+    }
+
+    /// Call function with current value of context `T`, when future completes.
+    ///
+    /// Callback is called from the `poll`, which returned [`Poll::Ready`], before result is returned
+    /// to the caller, so it sees contexts, set by wrappers around returned future (but not inside of
+    /// it), which is useful for emitting "request completed" events.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// use tyco::{context, FutureExt, TypedContext};
+    ///
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// struct RequestId(u64);
+    ///
+    /// context!(RequestId);
+    ///
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// async { /* handle request */ }
+    ///     .with_on_ready(|request_id: Option<RequestId>| {
+    ///         println!("request {:?} completed", request_id);
+    ///     })
+    ///     .with(RequestId(1))
+    ///     .await;
+    /// # });
+    /// # }
+    /// ```
+    fn with_on_ready<T: TypedContext, F: FnOnce(Option<T>)>(self, f: F) -> WithOnReady<Self, T, F> {
+        WithOnReady {
+            inner: self,
+            on_ready: Some(f),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Clear context while future is polled.
+    ///
+    /// Useful for futures, which must not inherit context of the caller (like background tasks,
+    /// not related to current request). Note that [`FutureExt::with_opt`] with `None` does not clear
+    /// context, it just leaves whatever is set at the time of poll.
+    fn without<T: TypedContext>(self) -> WithoutContext<Self, T> {
+        WithoutContext {
+            inner: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Keep guard alive as long as the future exists.
+    ///
+    /// Unlike [`FutureExt::with`], context is not attached on each poll, it stays set by the guard
+    /// until the future is completed and dropped (so it is also visible outside of the future).
+    /// Since [`ContextGuard`] is bound to the thread it was created on, the resulting future is `!Send`.
+    fn with_context_guard<T: TypedContext>(
+        self,
+        guard: ContextGuard<T>,
+    ) -> WithOwnedGuard<Self, T> {
+        WithOwnedGuard { inner: self, guard }
+    }
+
+    /// Set value as context for future and for its waker.
+    ///
+    /// Unlike [`FutureExt::with`], context is also set while waker, passed to the future, is woken
+    /// (see [`ContextAwareWaker`]). Waker is wrapped once and reused while executor passes the same
+    /// waker.
+    fn with_context_waker<T: TypedContext + Send + Sync>(
+        self,
+        value: T,
+    ) -> WithContextWaker<Self, T> {
+        WithContextWaker::new(self, value)
+    }
+
+    /// Set all values of the bundle as context for future.
+    ///
+    /// See [`context_bundle!`] for details.
+    fn with_bundle<B: ContextBundle>(self, bundle: B) -> WithContextBundle<Self, B> {
+        WithContextBundle::new(self, bundle)
+    }
+
+    /// Enter span and set its ID as [`SpanContext`] while future is polled.
+    ///
+    /// Requires `tracing` feature.
+    #[cfg(feature = "tracing")]
+    fn with_span(self, span: tracing::Span) -> WithSpan<Self> {
+        WithSpan::new(self, span)
+    }
+
+    /// Capture current span and enter it while future is polled, the same way as
+    /// [`with_span`](FutureExt::with_span).
+    ///
+    /// Can be used instead of `tracing::Instrument::in_current_span`. Requires `tracing` feature.
+    #[cfg(feature = "tracing")]
+    fn with_current_span(self) -> WithSpan<Self> {
+        WithSpan::new(self, tracing::Span::current())
+    }
+
+    /// Push value on the context stack while future is polled.
+    #[cfg(feature = "std")]
+    fn with_pushed<T: StackedContext>(self, value: T) -> WithContextStack<Self, T> {
+        WithContextStack::new(self, value)
+    }
+
+    /// Capture values of all contexts, registered with [`ContextSnapshot::register`], and set them
+    /// as context for future.
+    ///
+    /// Useful for library code, which doesn't know which context types application uses. Contexts,
+    /// which are not registered before this call, are not propagated. Values are captured once and
+    /// set by reference on each poll, without cloning.
+    #[cfg(feature = "std")]
+    fn with_captured(self) -> WithCaptured<Self> {
+        WithCaptured::new(self)
+    }
+}
+
+impl<T: Sized + Future<Output = O>, O> FutureExt for T {}
+
+/// Set value as the current context for a future.
+///
+/// Same as [`FutureExt::with`] and [`TypedContext::scope`], expressed as a function call. This is the
+/// way to set context in async code: unlike [`ContextGuard`], held across `.await`, value is set only
+/// while the future is polled, so it doesn't leak to other tasks, running on the same thread.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context, with_temporary_async, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct RequestId(u64);
+///
+/// context!(RequestId);
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let res = with_temporary_async(RequestId(1), async {
+///     tokio::task::yield_now().await;
+///     RequestId::current()
+/// })
+/// .await;
+///
+/// assert_eq!(res, Some(RequestId(1)));
+/// # });
+/// # }
+/// ```
+pub fn with_temporary_async<T: TypedContext, F: Future>(value: T, f: F) -> WithContext<F, T> {
+    f.with(value)
+}
+
+/// Macro for implementing typed context.
+///
+/// This macro will generate impmenetation of [`TypedContext`] trait
+/// for your type alongside with necessary TLS definitions. Macro accept
+/// path to type and optional name of TLS variable.
+///
+/// Alternatively static with custom [`TlsBackend`] can be defined:
+/// `context!(Type, static NAME: Backend = init)`. This is the only form, available without `std` feature.
+///
+/// # Default value
+///
+/// By default there is no value in context until it is attached. Default value can be provided with
+/// `context!(Type, default = expr)` (or `context!(Type, TLS_NAME, default = expr)`): expression is
+/// evaluated once per thread on first access, so [`TypedContext::current`] returns `Some` unless value is
+/// explicitly cleared with [`TypedContext::take`]. Default value is not considered set by
+/// [`TypedContext::is_set`], but it is restored by guards like any other value.
+///
+/// # TLS variable name
+///
+/// If name of TLS variable is not passed, variable is defined inside of anonymous `const _` block, so it
+/// is not visible outside of the macro and macro can be used any number of times in one module. Explicitly
+/// named variables are defined in the module, where macro is called, and must be unique within it.
+///
+/// In both cases each invocation defines its own variable: statics are distinct items even if crates or
+/// modules have the same names, so contexts, defined in different crates, never share storage. Migration
+/// from explicit names is not required, storage is still per-thread and behaves the same way.
+///
+/// # Generic types
+///
+/// Thread-local storage can't be generic, so context can be implemented only for concrete instantiations of
+/// generic types, each with its own TLS variable (`context!(Wrapper<u32>, WRAPPER_U32_CONTEXT)`).
+/// Generic form `context!(Wrapper<T> where T: Clone + 'static)` produces compile error.
+///
+/// # Visibility
+///
+/// TLS variable is private to the module where macro is invoked and is accessed only through
+/// [`TypedContext::tls`], so type can be defined in private module and re-exported. Values are never
+/// constructed by the crate (they are only cloned), so `#[non_exhaustive]` types and types with
+/// private fields are supported too.
+///
+/// # Example:
+///
+/// ```no_run
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context, FutureExt, TypedContext};
+///
+/// mod trace_id {
+///     use super::*;
+///
+///     #[derive(Clone, Default, Debug, PartialEq)]
+///     pub struct TraceId(String);
+///
+///     tyco::context!(TraceId);
+/// }
+///
+/// mod request {
+///     #[derive(Clone, Debug, PartialEq)]
+///     pub struct RequestId(u64);
+///
+///     #[derive(Clone, Debug, PartialEq)]
+///     pub struct UserId(u64);
+///
+///     tyco::context!(RequestId, REQUEST_ID_CONTEXT);
+///     tyco::context!(UserId, USER_ID_CONTEXT);
+/// }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context {
+    ($name:path where $($bounds:tt)*) => {
+        compile_error!(
+            "context can't be implemented for generic type, because thread-local storage can't be generic; \
+             use `context!` for each concrete type instead, like `context!(Wrapper<u32>, WRAPPER_U32_CONTEXT)`"
+        );
+    };
+    ($name:path, static $backend:ident: $backend_ty:ty = $init:expr) => {
+        static $backend: $backend_ty = $init;
+
+        impl $crate::TypedContext for $name {
+            type Backend = $backend_ty;
+
+            fn tls() -> &'static Self::Backend {
+                &$backend
+            }
+
+            $crate::__context_counters!();
+        }
+    };
+    ($name:path) => {
+        const _: () = {
+            $crate::context!($name, CURRENT_CONTEXT_VALUE);
+        };
+    };
+    ($name:path, default = $default:expr) => {
+        const _: () = {
+            $crate::context!($name, CURRENT_CONTEXT_VALUE, default = $default);
+        };
+    };
+    ($name:path, $tls:ident) => {
+        $crate::__thread_local_context!($name, $tls);
+    };
+    ($name:path, $tls:ident, default = $default:expr) => {
+        $crate::__thread_local_context!($name, $tls, $default);
+    };
+}
+
+/// Macro for implementing typed context for newtypes.
+///
+/// Defines a newtype wrapper with [`Deref`]/[`DerefMut`](core::ops::DerefMut) to the inner type,
+/// `From` conversions in both directions and [`FromStr`](core::str::FromStr) (if inner type
+/// implements it), and implements [`TypedContext`] for it. It is useful both for reducing boilerplate
+/// of simple contexts and for types from other crates, since orphan rules forbid implementing
+/// [`TypedContext`] for foreign types. Wrapper always derives `Clone`, additional attributes are
+/// passed as is. Optional name of TLS variable can be passed the same way as for [`context!`].
+///
+/// Macro accepts either definition of tuple struct with single field, or shorthand form
+/// `Name(Inner)`, which makes the field public. Other kinds of structs are rejected, use [`context!`]
+/// for them.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use std::time::Instant;
+///
+/// use tyco::{context_newtype, TypedContext};
+///
+/// context_newtype! {
+///     #[derive(Debug, PartialEq)]
+///     pub struct TraceId(String), TRACE_ID_CONTEXT;
+/// }
+///
+/// context_newtype!(
+///     #[derive(Debug)]
+///     pub StartedAt(Instant)
+/// );
+///
+/// let _guard = "my-trace-id".parse::<TraceId>().unwrap().attach();
+/// assert_eq!(TraceId::current().as_deref().map(String::as_str), Some("my-trace-id"));
+///
+/// let _guard = StartedAt::from(Instant::now()).attach();
+/// let elapsed = StartedAt::current().map(|x| x.elapsed());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context_newtype {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $wrapper:ident($field_vis:vis $inner:ty) $(, $tls:ident)?;
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone)]
+        $vis struct $wrapper($field_vis $inner);
+
+        impl ::core::ops::Deref for $wrapper {
+            type Target = $inner;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl ::core::ops::DerefMut for $wrapper {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+
+        impl ::core::convert::From<$inner> for $wrapper {
+            fn from(value: $inner) -> Self {
+                Self(value)
+            }
+        }
+
+        impl ::core::convert::From<$wrapper> for $inner {
+            fn from(value: $wrapper) -> Self {
+                value.0
+            }
+        }
+
+        // Higher-ranked bound makes it non-trivial, so impl is just skipped if inner type is not `FromStr`.
+        impl ::core::str::FromStr for $wrapper
+        where
+            for<'a> $inner: ::core::str::FromStr,
+        {
+            type Err = <$inner as ::core::str::FromStr>::Err;
+
+            fn from_str(s: &str) -> ::core::result::Result<Self, Self::Err> {
+                s.parse().map(Self)
+            }
+        }
+
+        $crate::context!($wrapper $(, $tls)?);
+    };
+    ($(#[$meta:meta])* $vis:vis struct $wrapper:ident $($rest:tt)*) => {
+        compile_error!(
+            "`context_newtype!` supports only tuple structs with single field, use `context!` for other types"
+        );
+    };
+    ($(#[$meta:meta])* $vis:vis $wrapper:ident($inner:ty) $(, $tls:ident)?) => {
+        $crate::context_newtype! {
+            $(#[$meta])*
+            $vis struct $wrapper(pub $inner) $(, $tls)?;
+        }
+    };
+}
+
+/// Macro for implementing typed context for enums with unit variants.
+///
+/// Any `Clone` enum can be used with [`context!`], this macro additionally derives `Clone`, `Copy`,
+/// `Default` (variant should be marked with `#[default]`) and implements
+/// [`Display`](core::fmt::Display), which writes name of the variant. Optional name of TLS
+/// variable can be passed after the enum.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context_enum, TypedContext};
+///
+/// context_enum! {
+///     #[derive(Debug, PartialEq)]
+///     pub enum LogLevel {
+///         Trace,
+///         Debug,
+///         #[default]
+///         Info,
+///         Warn,
+///         Error,
+///     }, LOG_LEVEL_CONTEXT;
+/// }
+///
+/// assert_eq!(LogLevel::current_or_default(), LogLevel::Info);
+///
+/// let _guard = LogLevel::Debug.attach();
+/// assert_eq!(LogLevel::current(), Some(LogLevel::Debug));
+/// assert_eq!(LogLevel::Debug.to_string(), "Debug");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident),* $(,)?
+        } $(, $tls:ident)? $(;)?
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Default)]
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant,)*
+        }
+
+        impl ::core::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.write_str(match self {
+                    $(Self::$variant => ::core::stringify!($variant),)*
+                })
+            }
+        }
+
+        $crate::context!($name $(, $tls)?);
+    };
+}
+
+/// Macro for implementing typed context for structs with named fields, alongside with builder.
+///
+/// Defines the struct (deriving `Clone`, additional attributes are passed as is), implements
+/// [`TypedContext`] for it and defines builder with setter for each field, which accepts anything
+/// convertible into field type. Since `macro_rules!` can't construct identifiers, name of
+/// the builder is passed explicitly after the struct. Optional name of TLS variable can be passed
+/// after the builder name.
+///
+/// # Panics
+///
+/// `build` panics if some field was not set, `try_build` can be used to handle it.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context_with_builder, TypedContext};
+///
+/// context_with_builder! {
+///     #[derive(Debug, PartialEq)]
+///     pub struct TraceId {
+///         pub id: String,
+///         pub sampled: bool,
+///     }, builder = TraceIdBuilder, TRACE_ID_CONTEXT;
+/// }
+///
+/// let _guard = TraceIdBuilder::new().id("abc").sampled(true).build().attach();
+///
+/// assert_eq!(TraceId::current().map(|x| x.id), Some("abc".to_string()));
+/// assert!(TraceId::builder().id("abc").try_build().is_none());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context_with_builder {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }, builder = $builder:ident $(, $tls:ident)? $(;)?
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone)]
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty,)*
+        }
+
+        impl $name {
+            /// Create builder of the context value.
+            #[allow(dead_code)]
+            $vis fn builder() -> $builder {
+                $builder::new()
+            }
+        }
+
+        #[doc = ::core::concat!("Builder of [`", ::core::stringify!($name), "`].")]
+        #[derive(Default)]
+        #[must_use]
+        $vis struct $builder {
+            $($field: ::core::option::Option<$ty>,)*
+        }
+
+        #[allow(dead_code)]
+        impl $builder {
+            /// Create builder with no fields set.
+            $vis fn new() -> Self {
+                ::core::default::Default::default()
+            }
+
+            $(
+                #[doc = ::core::concat!("Set `", ::core::stringify!($field), "` field.")]
+                $vis fn $field(mut self, value: impl ::core::convert::Into<$ty>) -> Self {
+                    self.$field = ::core::option::Option::Some(value.into());
+                    self
+                }
+            )*
+
+            /// Build the value, returning `None` if some field was not set.
+            $vis fn try_build(self) -> ::core::option::Option<$name> {
+                ::core::option::Option::Some($name {
+                    $($field: self.$field?,)*
+                })
+            }
+
+            /// Build the value.
+            ///
+            /// # Panics
+            ///
+            /// Panics if some field was not set.
+            $vis fn build(self) -> $name {
+                $name {
+                    $($field: self.$field.unwrap_or_else(|| {
+                        ::core::panic!(
+                            "field `{}` of `{}` is not set",
+                            ::core::stringify!($field),
+                            ::core::stringify!($name),
+                        )
+                    }),)*
+                }
+            }
+        }
+
+        $crate::context!($name $(, $tls)?);
+    };
+}
+
+/// Macro for implementing typed context for types with lifetime parameter.
+///
+/// Borrowed types can't be stored in TLS, so context is implemented for owned counterpart
+/// (`context_with_projection!(RequestRef<'_>, Request)` works like `context!(Request)`, optional
+/// name of TLS variable is accepted as third argument), and borrowed type gets methods to read it:
+/// `current_owned` returns clone of owned value, and `borrow_current` calls closure with borrowed
+/// view of the current value, constructed with `From<&Owned>`, without cloning it.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context_with_projection, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct Request {
+///     path: String,
+/// }
+///
+/// pub struct RequestRef<'a> {
+///     path: &'a str,
+/// }
+///
+/// impl<'a> From<&'a Request> for RequestRef<'a> {
+///     fn from(value: &'a Request) -> Self {
+///         Self { path: &value.path }
+///     }
+/// }
+///
+/// context_with_projection!(RequestRef<'_>, Request);
+///
+/// let _guard = Request { path: "/users".into() }.attach();
+///
+/// assert_eq!(RequestRef::borrow_current(|x| x.map(|x| x.path.len())), Some(6));
+/// assert_eq!(RequestRef::current_owned(), Some(Request { path: "/users".into() }));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context_with_projection {
+    ($borrowed:ident<'_>, $owned:path $(, $tls:ident)?) => {
+        $crate::context!($owned $(, $tls)?);
+
+        impl $borrowed<'_> {
+            /// Get clone of the current owned value.
+            #[allow(dead_code)]
+            pub fn current_owned() -> ::core::option::Option<$owned> {
+                <$owned as $crate::TypedContext>::current()
+            }
+
+            /// Call function with borrowed view of the current value, without cloning it.
+            #[allow(dead_code)]
+            pub fn borrow_current<R>(
+                f: impl for<'a> ::core::ops::FnOnce(::core::option::Option<$borrowed<'a>>) -> R,
+            ) -> R {
+                <$owned as $crate::TypedContext>::with_current_ref(|x| {
+                    f(x.map(<$borrowed<'_> as ::core::convert::From<&$owned>>::from))
+                })
+            }
+        }
+    };
+}
+
+/// Macro for using types with lifetime parameter as contexts through owned counterpart.
+///
+/// Extends [`context_with_projection!`] (and accepts the same arguments) with methods, which allow
+/// to set context from borrowed value: `attach` and `scoped` convert value into owned type with
+/// `From<Borrowed<'_>>` and set it as context, so code, which works with borrowed values, doesn't
+/// need to know about owned type. Current value is read with `borrow_current`, which converts owned
+/// value back with `From<&Owned>`.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::context_cell_ref;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct RequestVec(Vec<u8>);
+///
+/// pub struct RequestSlice<'a>(&'a [u8]);
+///
+/// impl From<RequestSlice<'_>> for RequestVec {
+///     fn from(value: RequestSlice<'_>) -> Self {
+///         Self(value.0.to_vec())
+///     }
+/// }
+///
+/// impl<'a> From<&'a RequestVec> for RequestSlice<'a> {
+///     fn from(value: &'a RequestVec) -> Self {
+///         Self(&value.0)
+///     }
+/// }
+///
+/// context_cell_ref!(RequestSlice<'_>, RequestVec);
+///
+/// let body = b"GET / HTTP/1.1".as_slice();
+/// let _guard = RequestSlice(body).attach();
+///
+/// assert_eq!(RequestSlice::borrow_current(|x| x.map(|x| x.0.len())), Some(body.len()));
+/// assert_eq!(RequestSlice::current_owned(), Some(RequestVec(body.to_vec())));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! context_cell_ref {
+    ($borrowed:ident<'_>, $owned:path $(, $tls:ident)?) => {
+        $crate::context_with_projection!($borrowed<'_>, $owned $(, $tls)?);
+
+        impl $borrowed<'_> {
+            /// Convert value into owned type and set it as current context.
+            #[allow(dead_code)]
+            pub fn attach(self) -> $crate::ContextGuard<$owned> {
+                <$owned as $crate::TypedContext>::attach(::core::convert::From::from(self))
+            }
+
+            /// Convert value into owned type and call function with it set as current context.
+            #[allow(dead_code)]
+            pub fn scoped<R>(self, f: impl ::core::ops::FnOnce() -> R) -> R {
+                <$owned as $crate::TypedContext>::scoped(::core::convert::From::from(self), f)
+            }
+        }
+    };
+}
+
+/// Macro for implementing typed context, which can be passed between threads.
+///
+/// Same as [`context!`] (and accepts the same arguments), but additionally checks at compile time
+/// that type is `Send`, so error is reported at the definition of the context rather than at
+/// the place, where it is moved to another thread (like [`spawn_with_context`] or
+/// [`FutureExt::with`] with `tokio::spawn`).
+///
+/// # Example:
+///
+/// ```compile_fail
+/// use std::rc::Rc;
+///
+/// #[derive(Clone)]
+/// struct TraceId(Rc<str>);
+///
+/// tyco::context_send!(TraceId); // `Rc<str>` cannot be sent between threads safely
+/// ```
+#[macro_export]
+macro_rules! context_send {
+    ($name:path $(, $($args:tt)*)?) => {
+        const _: fn() = || {
+            fn assert_send_context<T: ::core::marker::Send>() {}
+            assert_send_context::<$name>();
+        };
+
+        $crate::context!($name $(, $($args)*)?);
+    };
+}
+
+/// Macro for propagating current values of several contexts into a future.
+///
+/// `propagate!(fut; A, B)` expands to `fut.with_current::<A>().with_current::<B>()`, so for single
+/// type there is only one wrapper. Can be used in expression position with any future expression,
+/// including `async` blocks.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use tyco::{context, propagate, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(String);
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct UserId(u64);
+///
+/// context!(TraceId, TRACE_ID_CONTEXT);
+/// context!(UserId, USER_ID_CONTEXT);
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let _trace_guard = TraceId("trace".into()).attach();
+/// let _user_guard = UserId(1).attach();
+///
+/// let task = tokio::spawn(propagate!(async { (TraceId::current(), UserId::current()) }; TraceId, UserId));
+/// assert_eq!(task.await.unwrap(), (Some(TraceId("trace".into())), Some(UserId(1))));
+/// # });
+/// # }
+/// ```
+#[macro_export]
+macro_rules! propagate {
+    (@wrap $fut:expr;) => {
+        $fut
+    };
+    (@wrap $fut:expr; $ty:path $(, $rest:path)*) => {
+        $crate::propagate!(@wrap $crate::FutureExt::with_current::<$ty>($fut); $($rest),*)
+    };
+    ($fut:expr; $($ty:path),+ $(,)?) => {
+        $crate::propagate!(@wrap $fut; $($ty),+)
+    };
+}
+
+#[cfg(feature = "metrics")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __context_counters {
+    () => {
+        fn counters() -> &'static $crate::ContextCounters {
+            static COUNTERS: $crate::ContextCounters = $crate::ContextCounters::new();
+            &COUNTERS
+        }
+    };
+}
+
+#[cfg(not(feature = "metrics"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __context_counters {
+    () => {};
+}
+
+#[cfg(feature = "std")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __thread_local_context {
+    (@init) => {
+        $crate::ContextSlot::new()
+    };
+    (@init $default:expr) => {
+        $crate::ContextSlot::with_default($default)
+    };
+    ($name:path, $tls:ident $(, $default:expr)?) => {
+        thread_local! {
+            static $tls: std::cell::RefCell<$crate::ContextSlot<$name>> =
+                std::cell::RefCell::new($crate::__thread_local_context!(@init $($default)?));
+        }
+
+        impl $crate::TypedContext for $name {
+            type Backend =
+                std::thread::LocalKey<std::cell::RefCell<$crate::ContextSlot<Self>>>;
+
+            fn tls() -> &'static Self::Backend {
+                &$tls
+            }
+
+            $crate::__context_counters!();
+        }
+    };
+}
+
+#[cfg(not(feature = "std"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __thread_local_context {
+    ($name:path, $tls:ident $(, $default:expr)?) => {
+        compile_error!(
+            "thread-local contexts require `std` feature of `tyco`; \
+             use `context!(Type, static NAME: Backend = init)` with custom backend instead"
+        );
+    };
+}
+
+#[cfg(all(test, feature = "std"))]
+mod ui_test {
+    use std::{
+        cell::RefCell,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    };
+
+    use super::{ContextGuard, FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Deadline(Instant);
+
+    impl Deadline {
+        pub fn after(after: Duration) -> Self {
+            Self(Instant::now() + after)
+        }
+
+        pub fn after_secs(after_secs: u64) -> Self {
+            Self::after(Duration::from_secs(after_secs))
+        }
+    }
+
+    context!(Deadline);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Priority(u8);
+
+    context!(Priority, PRIORITY_CONTEXT);
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct Tagged<T>(T);
+
+    context!(Tagged<u32>, TAGGED_U32_CONTEXT);
+    context!(Tagged<String>, TAGGED_STRING_CONTEXT);
+
+    #[test]
+    #[allow(clippy::drop_non_drop)]
+    fn both_attach() {
+        let x1 = Deadline::after_secs(1);
+        let _x1_guard = x1.clone().attach();
+
+        assert_eq!(Deadline::current().unwrap(), x1);
+
+        let x2 = Deadline::after_secs(2);
+        let x2_guard = unsafe { x2.attach_ref() };
+
+        assert_eq!(Deadline::current().unwrap(), x2);
+
+        drop(x2_guard);
+        drop(x2);
+
+        assert_eq!(Deadline::current().unwrap(), x1);
+    }
+
+    #[test]
+    fn custom_tls_name() {
+        let x = Deadline::after_secs(1);
+        let _x_guard = x.clone().attach();
+        let _priority_guard = Priority(1).attach();
+
+        assert_eq!(Deadline::current(), Some(x));
+        assert_eq!(Priority::current(), Some(Priority(1)));
+        PRIORITY_CONTEXT.with(|v| assert!(v.borrow().is_some()));
+    }
+
+    mod custom_backend {
+        use std::{cell::RefCell, sync::Mutex};
+
+        use crate::{ContextSlot, TlsBackend};
+
+        /// Backend, shared between all threads.
+        pub struct Global<T: 'static>(Mutex<RefCell<ContextSlot<T>>>);
+
+        impl<T> Global<T> {
+            pub const fn new() -> Self {
+                Self(Mutex::new(RefCell::new(ContextSlot::new())))
+            }
+        }
+
+        impl<T> TlsBackend<T> for Global<T> {
+            const SHARED: bool = true;
+
+            fn try_with<R>(
+                &'static self,
+                f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R,
+            ) -> Option<R> {
+                Some(f(&self.0.lock().unwrap()))
+            }
+        }
+
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Region(pub &'static str);
+
+        context!(Region, static REGION: Global<Region> = Global::new());
+    }
+
+    #[test]
+    fn custom_backend() {
+        use custom_backend::Region;
+
+        let _guard = Region("eu").attach();
+
+        let region = std::thread::spawn(Region::current).join().unwrap();
+        assert_eq!(region, Some(Region("eu")));
+    }
+
+    context_newtype!(
+        #[derive(Debug, PartialEq)]
+        Timeout(std::time::Duration),
+        TIMEOUT_CONTEXT
+    );
+
+    context_newtype! {
+        #[derive(Debug, PartialEq)]
+        struct TraceId(String), TRACE_ID_CONTEXT;
+    }
+
+    #[test]
+    fn newtype_from_str() -> Result<(), std::convert::Infallible> {
+        let _guard = "my-trace-id".parse::<TraceId>()?.attach();
+
+        assert_eq!(
+            TraceId::current(),
+            Some(TraceId::from(String::from("my-trace-id")))
+        );
+        assert_eq!(TraceId::with_current_ref(|x| x.map(|x| x.len())), Some(11));
+        Ok(())
+    }
+
+    #[test]
+    fn newtype() {
+        let _guard = Timeout::from(std::time::Duration::from_secs(1)).attach();
+
+        let mut timeout = Timeout::current().unwrap();
+        assert_eq!(timeout.as_secs(), 1);
+
+        *timeout += std::time::Duration::from_secs(1);
+        assert_eq!(
+            std::time::Duration::from(timeout),
+            std::time::Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn current_or_default() {
+        assert_eq!(Tagged::<u32>::current_or_default(), Tagged(0));
+        assert!(!Tagged::<u32>::is_set());
+
+        let _guard = Tagged(1u32).attach();
+        assert_eq!(Tagged::<u32>::current_or_default(), Tagged(1));
+    }
+
+    #[test]
+    fn current_or_insert_default() {
+        let guard = Tagged(String::from("outer")).attach();
+        Tagged::<String>::take();
+
+        assert_eq!(
+            Tagged::<String>::current_or_insert_default(),
+            Tagged(String::new())
+        );
+        assert!(Tagged::<String>::is_set());
+        Tagged::<String>::update(|x| x.0.push_str("inner"));
+        assert_eq!(
+            Tagged::<String>::current_or_insert_default(),
+            Tagged(String::from("inner"))
+        );
+
+        drop(guard);
+        assert_eq!(Tagged::<String>::current(), None);
+    }
+
+    #[test]
+    fn guard_into_value() {
+        let outer = Tagged(String::from("outer")).attach();
+        let inner = Tagged(String::from("inner")).attach();
+        Tagged::<String>::update(|x| x.0.push_str(" updated"));
+
+        assert_eq!(inner.into_value(), Tagged(String::from("inner updated")));
+        assert_eq!(
+            Tagged::<String>::current(),
+            Some(Tagged(String::from("outer")))
+        );
+
+        Tagged::<String>::take();
+        assert_eq!(outer.into_value(), Tagged(String::from("outer")));
+        assert_eq!(Tagged::<String>::current(), None);
+    }
+
+    #[test]
+    fn update_clones() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq)]
+        struct Counted(u32);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Self(self.0)
+            }
+        }
+
+        context!(Counted);
+
+        let value = Counted(1);
+        let ref_guard = unsafe { value.attach_ref() };
+        Counted::update(|x| x.0 += 1);
+        Counted::update(|x| x.0 += 1);
+        assert_eq!(CLONES.load(Ordering::Relaxed), 1);
+        assert_eq!(value, Counted(1));
+        drop(ref_guard);
+
+        // Attached value is shared with the guard, so it is cloned too.
+        let _guard = Counted(1).attach();
+        Counted::update(|x| x.0 += 1);
+        Counted::update(|x| x.0 += 1);
+        assert_eq!(CLONES.load(Ordering::Relaxed), 2);
+
+        Counted::replace(Counted(1));
+        Counted::update(|x| x.0 += 1);
+        assert_eq!(CLONES.load(Ordering::Relaxed), 2);
+        assert_eq!(Counted::take().map(|x| x.0), Some(2));
+    }
+
+    #[test]
+    fn modify_clones() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq)]
+        struct Counted(u32);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Self(self.0)
+            }
+        }
+
+        context!(Counted);
+
+        let value = Counted(1);
+        let ref_guard = unsafe { value.attach_ref() };
+        Counted::modify(|x| x.0 += 1);
+        Counted::modify(|x| x.0 += 1);
+        assert_eq!(CLONES.load(Ordering::Relaxed), 1);
+        assert_eq!(value, Counted(1));
+        drop(ref_guard);
+
+        let mut guard = Counted(1).attach();
+        assert!(guard.modify(|x| x.0 += 1));
+        assert!(guard.modify(|x| x.0 += 1));
+        assert_eq!(CLONES.load(Ordering::Relaxed), 1);
+        assert_eq!(guard.value(), Some(&Counted(3)));
+        assert!(Counted::with_current_ref(|x| x == Some(&Counted(3))));
+
+        let inner = Counted(10).attach();
+        assert!(!guard.modify(|x| x.0 += 1));
+        drop(inner);
+
+        assert_eq!(guard.detach(), Counted(3));
+        assert_eq!(CLONES.load(Ordering::Relaxed), 1);
+        assert!(!Counted::is_set());
+    }
+
+    #[test]
+    fn guard_from_value() {
+        let (guard, value) = ContextGuard::from_value(Tagged(String::from("shared")));
+
+        assert_eq!(value.0, "shared");
+        assert!(Tagged::<String>::with_current_ref(|x| std::ptr::eq(
+            x.unwrap(),
+            &*value
+        )));
+        assert!(std::ptr::eq(guard.value().unwrap(), &*value));
+    }
+
+    #[test]
+    fn ref_guard_upgrade() {
+        let _outer = Priority(1).attach();
+
+        let guard = {
+            let value = Priority(2);
+            let ref_guard = unsafe { value.attach_ref() };
+            Priority::update(|x| x.0 += 1);
+            ref_guard.upgrade()
+        };
+
+        assert_eq!(Priority::current(), Some(Priority(3)));
+        assert_eq!(guard.value(), Some(&Priority(3)));
+
+        drop(guard);
+        assert_eq!(Priority::current(), Some(Priority(1)));
+    }
+
+    #[test]
+    fn current_version() {
+        let initial = Priority::current_version();
+        assert_eq!(Priority::current_if_changed(initial), None);
+
+        let guard = Priority(1).attach();
+        let (value, version) = Priority::current_if_changed(initial).unwrap();
+        assert_eq!(value, Priority(1));
+        assert_ne!(version, initial);
+        assert_eq!(Priority::current_if_changed(version), None);
+
+        Priority::update(|x| x.0 += 1);
+        let (value, version) = Priority::current_if_changed(version).unwrap();
+        assert_eq!(value, Priority(2));
+        assert_eq!(Priority::current_version(), version);
+
+        drop(guard);
+        assert_ne!(Priority::current_version(), version);
+        assert_eq!(Priority::current_if_changed(version), None);
+    }
+
+    #[test]
+    fn nesting_depth() {
+        assert_eq!(Priority::nesting_depth(), 0);
+
+        let outer = Priority(1).attach();
+        let inner = Priority(2).attach();
+        {
+            let value = Priority(3);
+            let _ref_guard = unsafe { value.attach_ref() };
+            assert_eq!(Priority::nesting_depth(), 3);
+        }
+
+        Priority::replace(Priority(4));
+        assert_eq!(Priority::nesting_depth(), 2);
+
+        assert_eq!(inner.into_value(), Priority(4));
+        assert_eq!(Priority::nesting_depth(), 1);
+        drop(outer);
+        assert_eq!(Priority::nesting_depth(), 0);
+    }
+
+    #[test]
+    fn attach_with_default() {
+        let guard = Priority::attach_with_default(Priority(1));
+        assert!(guard.is_some());
+        assert!(Priority::attach_with_default(Priority(2)).is_none());
+        assert_eq!(Priority::current(), Some(Priority(1)));
+
+        drop(guard);
+        assert_eq!(Priority::current(), None);
+    }
+
+    #[test]
+    fn guard_eq() {
+        let guard1 = Priority(1).attach();
+        let guard2 = Priority(1).attach();
+        let guard3 = Priority(2).attach();
+
+        assert!(guard1 == Priority(1));
+        assert!(guard1 == guard2);
+        assert!(guard2 != guard3);
+    }
+
+    #[test]
+    fn guard_suspend() {
+        let mut guard = Priority(1).attach();
+
+        {
+            let _suspended = guard.suspend();
+            assert_eq!(Priority::current(), None);
+
+            let _inner = Priority(2).attach();
+            assert_eq!(Priority::current(), Some(Priority(2)));
+        }
+        assert_eq!(Priority::current(), Some(Priority(1)));
+
+        drop(guard);
+        assert_eq!(Priority::current(), None);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Attempt(u32);
+
+    context_send!(Attempt, ATTEMPT_CONTEXT, default = Attempt(1));
+
+    #[test]
+    fn send_context() {
+        let _guard = Attempt(2).attach();
+
+        assert_eq!(
+            crate::spawn_with_context::<Attempt, _, _>(Attempt::current)
+                .join()
+                .unwrap(),
+            Some(Attempt(2))
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn attach_detach_counts() {
+        #[derive(Clone)]
+        struct Counted;
+
+        context!(Counted);
+
+        let guard = Counted.attach();
+        async {
+            tokio::task::yield_now().await;
+        }
+        .with(Counted)
+        .await;
+        guard.into_value();
+
+        assert_eq!(Counted::attach_count(), 3);
+        assert_eq!(Counted::detach_count(), 3);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn attach_counts_unavailable_storage() {
+        struct Unavailable;
+
+        impl<T: 'static> crate::TlsBackend<T> for Unavailable {
+            fn try_with<R>(
+                &'static self,
+                _: impl FnOnce(&RefCell<crate::ContextSlot<T>>) -> R,
+            ) -> Option<R> {
+                None
+            }
+        }
+
+        #[derive(Clone)]
+        struct Counted;
+
+        context!(Counted, static COUNTED: Unavailable = Unavailable);
+
+        let mut guard = Counted.attach();
+        drop(guard.reborrow());
+        Counted.scoped(|| {});
+        futures::executor::block_on(async {}.with(Counted));
+        drop(guard);
+        Counted.attach().into_value();
+
+        assert_eq!(Counted::attach_count(), 0);
+        assert_eq!(Counted::detach_count(), 0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn attach_counts_manual_impl() {
+        #[derive(Clone)]
+        struct Manual;
+
+        thread_local! {
+            static MANUAL: RefCell<crate::ContextSlot<Manual>> =
+                const { RefCell::new(crate::ContextSlot::new()) };
+        }
+
+        impl TypedContext for Manual {
+            type Backend = std::thread::LocalKey<RefCell<crate::ContextSlot<Manual>>>;
+
+            fn tls() -> &'static Self::Backend {
+                &MANUAL
+            }
+        }
+
+        // Counters are shared with other manual implementations, which may be attached concurrently.
+        let (attached, detached) = (Manual::attach_count(), Manual::detach_count());
+        Manual.attach().into_value();
+        assert!(Manual::attach_count() > attached);
+        assert!(Manual::detach_count() > detached);
+    }
+
+    #[test]
+    fn current_arc() {
+        assert_eq!(Tagged::<String>::current_arc(), None);
+
+        let guard = Tagged(String::from("large")).attach();
+        let value = Tagged::<String>::current_arc().unwrap();
+        assert!(std::ptr::eq(guard.value().unwrap(), &*value));
+
+        let inner = Tagged::attach_arc(value.clone());
+        assert!(std::ptr::eq(inner.value().unwrap(), &*value));
+        drop(inner);
+
+        let borrowed = Tagged(String::from("borrowed"));
+        let _ref_guard = unsafe { borrowed.attach_ref() };
+        assert_eq!(*Tagged::<String>::current_arc().unwrap(), borrowed);
+    }
+
+    #[test]
+    fn current_map() {
+        assert_eq!(Tagged::<String>::current_map(|x| x.0), None);
+
+        let _guard = Tagged(String::from("42")).attach();
+        assert_eq!(
+            Tagged::<String>::current_map(|x| x.0),
+            Some(String::from("42"))
+        );
+        assert_eq!(
+            Tagged::<String>::current_flat_map(|x| x.0.parse::<u32>().ok()),
+            Some(42)
+        );
+        assert_eq!(
+            Tagged::<String>::current_flat_map(|x| x.0.parse::<i8>().ok().filter(|x| *x < 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn format_without_clone() {
+        assert_eq!(Tagged::<u32>::with_debug(|x| format!("{x:?}")), None);
+
+        let _guard = Tagged(7u32).attach();
+        assert_eq!(
+            Tagged::<u32>::with_debug(|x| format!("{x:?}")),
+            Some(String::from("Tagged(7)"))
+        );
+    }
+
+    #[test]
+    fn generic_instantiations() {
+        let _u32_guard = Tagged(1u32).attach();
+        assert_eq!(Tagged::<String>::current(), None);
+
+        let _string_guard = Tagged(String::from("tag")).attach();
+        assert_eq!(Tagged::<u32>::current(), Some(Tagged(1)));
+        assert_eq!(Tagged::<String>::current(), Some(Tagged("tag".into())));
+    }
+
+    #[test]
+    fn guard_value() {
+        let x1 = Deadline::after_secs(1);
+        let x1_guard = x1.clone().attach();
+        assert_eq!(x1_guard.value(), Some(&x1));
+
+        let x2 = Deadline::after_secs(2);
+        let x2_guard = unsafe { x2.attach_ref() };
+        assert_eq!(x2_guard.value(), Deadline::current().as_ref());
+
+        drop(x2_guard);
+        assert_eq!(x1_guard.value(), Deadline::current().as_ref());
+    }
+
+    #[test]
+    fn guard_value_on_thread_destruction() {
+        struct AttachOnDrop(std::sync::mpsc::Sender<bool>);
+
+        impl Drop for AttachOnDrop {
+            fn drop(&mut self) {
+                let guard = Deadline::after_secs(1).attach();
+                let _ = self.0.send(guard.value().is_none());
+            }
+        }
+
+        thread_local! {
+            static ATTACH_ON_DROP: RefCell<Option<AttachOnDrop>> = const { RefCell::new(None) };
+        }
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            ATTACH_ON_DROP.with(|x| *x.borrow_mut() = Some(AttachOnDrop(tx)));
+            // Initialize context TLS after `ATTACH_ON_DROP`, so it is destroyed first
+            let _guard = Deadline::after_secs(2).attach();
+        })
+        .join()
+        .unwrap();
+
+        assert!(rx.recv().unwrap());
+    }
+
+    #[test]
+    fn poll_after_ready() {
+        let mut fut = std::pin::pin!(std::future::poll_fn(|_| Poll::Ready(Deadline::current()))
+            .with(Deadline::after_secs(1)));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert!(matches!(fut.as_mut().poll(&mut cx), Poll::Ready(Some(_))));
+        assert!(fut.value().is_none());
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn poll_after_ready_fn() {
+        let mut fut = std::pin::pin!(std::future::poll_fn(|_| Poll::Ready(Deadline::current()))
+            .with_fn(|| Deadline::after_secs(1)));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert!(matches!(fut.as_mut().poll(&mut cx), Poll::Ready(Some(_))));
+        assert!(fut.value.is_none());
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn poll_after_ready_multi() {
+        let mut fut = std::pin::pin!(std::future::poll_fn(|_| {
+            Poll::Ready((Deadline::current().is_some(), Priority::current()))
+        })
+        .with2(Deadline::after_secs(1), Priority(1)));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert_eq!(
+            fut.as_mut().poll(&mut cx),
+            Poll::Ready((true, Some(Priority(1))))
+        );
+        assert!(fut.value1.is_none() && fut.value2.is_none());
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready((false, None)));
+    }
+
+    #[test]
+    fn poll_after_ready_default() {
+        let mut fut =
+            std::pin::pin!(std::future::poll_fn(|_| Poll::Ready(Priority::current()))
+                .with_default(Priority(1)));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(Some(Priority(1))));
+        assert!(fut.value.is_none());
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(None));
+    }
+
+    #[tokio::test]
+    async fn with_on_ready() {
+        let completed = RefCell::new(Vec::new());
+
+        let res = async {
+            tokio::task::yield_now().await;
+            Priority::replace(Priority(2));
+            1
+        }
+        .with_on_ready(|x: Option<Priority>| completed.borrow_mut().push(x))
+        .with(Priority(1))
+        .await;
+        assert_eq!(res, 1);
+
+        async {}
+            .with_on_ready(|x: Option<Priority>| completed.borrow_mut().push(x))
+            .await;
+
+        assert_eq!(*completed.borrow(), vec![Some(Priority(2)), None]);
+    }
+
+    #[test]
+    fn guard_escaping_wrapper() {
+        let guard = futures::executor::block_on(
+            async { Tagged(String::from("inner")).attach() }.with(Tagged(String::from("outer"))),
+        );
+        // Wrapper restored previous value at the end of the poll and dropped its value on completion,
+        // while guard keeps its own copy of the value, which was current when it was created.
+        assert_eq!(Tagged::<String>::current(), None);
+        drop(guard);
+        assert_eq!(Tagged::<String>::current(), Some(Tagged("outer".into())));
+        assert_eq!(Tagged::<String>::take(), Some(Tagged("outer".into())));
+    }
+
+    #[test]
+    fn upgraded_guard_escaping_wrapper() {
+        let guard = futures::executor::block_on(
+            async {
+                let value = Tagged(String::from("inner"));
+                unsafe { value.attach_ref() }.upgrade()
+            }
+            .with(Tagged(String::from("outer"))),
+        );
+        assert_eq!(Tagged::<String>::current(), None);
+        drop(guard);
+        assert_eq!(Tagged::<String>::current(), Some(Tagged("outer".into())));
+        assert_eq!(Tagged::<String>::take(), Some(Tagged("outer".into())));
+    }
+
+    #[test]
+    fn poll_on_different_threads() {
+        let mut polled = false;
+        let mut fut = Box::pin(
+            std::future::poll_fn(move |_| {
+                if std::mem::replace(&mut polled, true) {
+                    Poll::Ready(Priority::current())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .with(Priority(3)),
+        );
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(Priority::current(), None);
+
+        let res = std::thread::spawn(move || {
+            let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+            (fut.as_mut().poll(&mut cx), Priority::current())
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(res, (Poll::Ready(Some(Priority(3))), None));
+        assert_eq!(Priority::current(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "`async fn` resumed after completion")]
+    fn poll_async_block_after_ready() {
+        let mut fut = std::pin::pin!(async { Deadline::current() }.with(Deadline::after_secs(1)));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert!(fut.as_mut().poll(&mut cx).is_ready());
+        let _ = fut.as_mut().poll(&mut cx);
+    }
+
+    #[test]
+    fn guard_reborrow() {
+        let x1 = Deadline::after_secs(1);
+        let x2 = Deadline::after_secs(2);
+        let _x1_guard = x1.clone().attach();
+        let mut x2_guard = x2.clone().attach();
+
+        {
+            let reborrowed = x2_guard.reborrow();
+            assert_eq!(reborrowed.value(), Some(&x2));
+            assert_eq!(Deadline::nesting_depth(), 3);
+
+            Deadline::replace(Deadline::after_secs(3));
+        }
+
+        assert_eq!(Deadline::current(), Some(x2));
+        assert_eq!(Deadline::nesting_depth(), 2);
+
+        drop(x2_guard);
+        assert_eq!(Deadline::current(), Some(x1));
+    }
+
+    #[test]
+    fn guard_reborrow_dropped_out_of_order() {
+        let mut guard = Tagged(String::from("a")).attach();
+        let reborrowed = guard.reborrow();
+        let other = Tagged(String::from("b")).attach();
+
+        drop(reborrowed);
+        assert_eq!(Tagged::<String>::current(), Some(Tagged("a".into())));
+        drop(guard);
+        assert_eq!(Tagged::<String>::current(), None);
+        // Value of the reborrowed guard is shared with TLS, so it is still alive.
+        drop(other);
+        assert_eq!(Tagged::<String>::current(), Some(Tagged("a".into())));
+        assert_eq!(Tagged::<String>::take(), Some(Tagged("a".into())));
+    }
+
+    #[test]
+    fn guard_as_ref() {
+        fn priority_level(priority: impl AsRef<Priority>) -> u8 {
+            priority.as_ref().0
+        }
+
+        let guard = Priority(5).attach();
+        Priority::replace(Priority(6));
+
+        assert_eq!(priority_level(&guard), 5);
+    }
+
+    #[test]
+    fn composite_guard() {
+        let x = Deadline::after_secs(1);
+        let _outer = Priority(1).attach();
+
+        let guard = x
+            .clone()
+            .attach()
+            .and_also(Priority(2))
+            .and_also(Tagged(3u32));
+        assert_eq!(
+            (
+                Deadline::current(),
+                Priority::current(),
+                Tagged::<u32>::current()
+            ),
+            (Some(x), Some(Priority(2)), Some(Tagged(3)))
+        );
+
+        let (rest, last) = guard.into_inner();
+        drop(last);
+        assert_eq!(Tagged::<u32>::current(), None);
+
+        drop(rest);
+        assert_eq!(
+            (Deadline::current(), Priority::current()),
+            (None, Some(Priority(1)))
+        );
+        assert_eq!(Priority::nesting_depth(), 1);
+    }
+
+    #[test]
+    fn guard_forget() {
+        let x = Deadline::after_secs(1);
+
+        {
+            x.clone().attach().forget();
+        }
+
+        assert_eq!(Deadline::current(), Some(x));
+        assert_eq!(Deadline::nesting_depth(), 1);
+    }
+
+    #[test]
+    fn guard_detach() {
+        let x1 = Deadline::after_secs(1);
+        let _x1_guard = x1.clone().attach();
+
+        let x2 = Deadline::after_secs(2);
+        let x2_guard = x2.clone().attach();
+
+        assert_eq!(x2_guard.detach(), x2);
+        assert_eq!(Deadline::current(), Some(x1));
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum LogLevel {
+        Info,
+        Debug,
+    }
+
+    context!(LogLevel, LOG_LEVEL_CONTEXT, default = LogLevel::Info);
+
+    #[test]
+    fn default_value() {
+        assert_eq!(LogLevel::current(), Some(LogLevel::Info));
+        assert!(!LogLevel::is_set());
+
+        let guard = LogLevel::Debug.attach();
+        assert_eq!(LogLevel::current(), Some(LogLevel::Debug));
+        assert!(LogLevel::is_set());
+        drop(guard);
+
+        assert_eq!(LogLevel::current(), Some(LogLevel::Info));
+        assert_eq!(
+            std::thread::spawn(LogLevel::current).join().unwrap(),
+            Some(LogLevel::Info)
+        );
+
+        LogLevel::update(|x| *x = LogLevel::Debug);
+        assert!(LogLevel::is_set());
+
+        assert_eq!(LogLevel::take(), Some(LogLevel::Debug));
+        assert_eq!(LogLevel::current(), None);
+    }
+
+    #[test]
+    fn is_set_and_current_or_else() {
+        assert!(!Priority::is_set());
+        assert_eq!(Priority::current_or_else(|| Priority(0)), Priority(0));
+
+        let _guard = Priority(1).attach();
+        assert!(Priority::is_set());
+        assert_eq!(Priority::current_or_else(|| Priority(0)), Priority(1));
+    }
+
+    #[test]
+    fn with_current_ref() {
+        assert_eq!(Priority::with_current_ref(|x| x.map(|x| x.0)), None);
+
+        let _guard = Priority(1).attach();
+        assert_eq!(Priority::with_current_ref(|x| x.map(|x| x.0)), Some(1));
+
+        let x = Priority(2);
+        let _ref_guard = unsafe { x.attach_ref() };
+        assert_eq!(Priority::with_current_ref(|x| x.map(|x| x.0)), Some(2));
+    }
+
+    #[test]
+    fn guard_swap() {
+        let _guard1 = Priority(1).attach();
+        let mut guard2 = Priority(2).attach();
+
+        assert_eq!(guard2.swap(Priority(3)), Priority(2));
+        assert_eq!(guard2.value(), Some(&Priority(3)));
+        assert_eq!(Priority::current(), Some(Priority(3)));
+
+        drop(guard2);
+        assert_eq!(Priority::current(), Some(Priority(1)));
+    }
+
+    #[test]
+    fn guard_clone() {
+        let guard1 = Priority(1).attach();
+        let mut guard2 = guard1.clone();
+
+        guard2.swap(Priority(2));
+        assert_eq!(Priority::current(), Some(Priority(2)));
+
+        drop(guard2);
+        assert_eq!(Priority::current(), Some(Priority(1)));
+
+        drop(guard1);
+        assert_eq!(Priority::current(), None);
+    }
+
+    #[test]
+    fn take() {
+        let x1 = Deadline::after_secs(1);
+        let _x1_guard = x1.clone().attach();
+
+        let x2 = Deadline::after_secs(2);
+        let x2_guard = x2.clone().attach();
+
+        assert_eq!(Deadline::take(), Some(x2));
+        assert_eq!(Deadline::current(), None);
+        assert_eq!(Deadline::take(), None);
+
+        drop(x2_guard);
+        assert_eq!(Deadline::current(), Some(x1));
+    }
+
+    #[test]
+    fn replace() {
+        let x1 = Deadline::after_secs(1);
+        assert_eq!(Deadline::replace(x1.clone()), None);
+
+        let x2 = Deadline::after_secs(2);
+        assert_eq!(Deadline::replace(x2.clone()), Some(x1));
+        assert_eq!(Deadline::current(), Some(x2));
+    }
+
+    #[test]
+    fn update() {
+        Priority::update(|x| x.0 += 1);
+        assert_eq!(Priority::current(), None);
+
+        let guard = Priority(1).attach();
+        Priority::update(|x| x.0 += 1);
+
+        assert_eq!(Priority::current(), Some(Priority(2)));
+        assert_eq!(guard.value(), Some(&Priority(1)));
+    }
+
+    #[test]
+    fn scoped_restores_on_panic() {
+        let x1 = Deadline::after_secs(1);
+        let _x1_guard = x1.clone().attach();
+
+        let x2 = Deadline::after_secs(2);
+        assert_eq!(x2.clone().scoped(Deadline::current), Some(x2.clone()));
+
+        let res = std::panic::catch_unwind(|| x2.scoped(|| panic!("oops")));
+        assert!(res.is_err());
+        assert_eq!(Deadline::current(), Some(x1));
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        assert_eq!(
+            Priority::compare_and_swap(&Priority(0), Priority(1)).err(),
+            Some(Priority(1))
+        );
+
+        let _guard = Priority(1).attach();
+        let outer = Priority::compare_and_swap(&Priority(1), Priority(2)).unwrap();
+
+        Priority::run_with(Priority(3), || {
+            // nested scope sees its own value
+            assert!(Priority::compare_and_swap(&Priority(2), Priority(4)).is_err());
+            let inner = Priority::compare_and_swap(&Priority(3), Priority(4)).unwrap();
+            assert_eq!(Priority::current(), Some(Priority(4)));
+            drop(inner);
+        });
+
+        assert_eq!(Priority::current(), Some(Priority(2)));
+        drop(outer);
+        assert_eq!(Priority::current(), Some(Priority(1)));
+        assert_eq!(Priority::nesting_depth(), 1);
+    }
+
+    #[test]
+    fn compare_and_swap_default() {
+        assert_eq!(LogLevel::current(), Some(LogLevel::Info));
+
+        let guard = LogLevel::compare_and_swap(&LogLevel::Info, LogLevel::Debug).unwrap();
+        assert_eq!(LogLevel::current(), Some(LogLevel::Debug));
+        assert!(LogLevel::is_set());
+
+        drop(guard);
+        assert!(!LogLevel::is_set());
+    }
+
+    #[test]
+    fn run_with_restores_on_panic() {
+        let _guard = Priority(1).attach();
+
+        assert_eq!(
+            Priority::run_with(Priority(2), Priority::current),
+            Some(Priority(2))
+        );
+
+        let res = std::panic::catch_unwind(|| {
+            Priority::run_with(Priority(3), || {
+                assert_eq!(Priority::current(), Some(Priority(3)));
+                panic!("oops")
+            })
+        });
+        assert!(res.is_err());
+        assert_eq!(Priority::current(), Some(Priority(1)));
+        assert_eq!(Priority::nesting_depth(), 1);
+    }
+
+    mod unnamed_tls {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct First(pub u8);
+
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Second(pub u8);
+
+        context!(First);
+        context!(Second, default = Second(0));
+    }
+
+    #[test]
+    fn unnamed_tls() {
+        use unnamed_tls::{First, Second};
+
+        let _guard = First(1).attach();
+        assert_eq!(Second::current(), Some(Second(0)));
+
+        Second(2).scoped(|| {
+            assert_eq!(
+                (First::current(), Second::current()),
+                (Some(First(1)), Some(Second(2)))
+            );
+        });
+    }
+
+    mod span_ids {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct ParentSpanId(pub u64);
+
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct ChildSpanId(pub u64);
+
+        context!(ParentSpanId);
+        context!(ChildSpanId);
+    }
+
+    #[test]
+    fn guard_map() {
+        use span_ids::{ChildSpanId, ParentSpanId};
+
+        let _parent = ParentSpanId(0).attach();
+        let _child = ChildSpanId(10).attach();
+
+        let guard = ParentSpanId(1).attach();
+        let mapped = guard.map(|x| {
+            assert_eq!(ChildSpanId::current(), Some(ChildSpanId(10)));
+            ChildSpanId(x.0 + 1)
+        });
+
+        assert_eq!(ParentSpanId::current(), Some(ParentSpanId(0)));
+        assert_eq!(ChildSpanId::current(), Some(ChildSpanId(2)));
+        assert_eq!(mapped.value(), Some(&ChildSpanId(2)));
+        assert_eq!(
+            (ParentSpanId::nesting_depth(), ChildSpanId::nesting_depth()),
+            (1, 2)
+        );
+
+        drop(mapped);
+        assert_eq!(ParentSpanId::current(), Some(ParentSpanId(0)));
+        assert_eq!(ChildSpanId::current(), Some(ChildSpanId(10)));
+        assert_eq!(
+            (ParentSpanId::nesting_depth(), ChildSpanId::nesting_depth()),
+            (1, 1)
+        );
+    }
+
+    #[test]
+    fn guard_with_scope() {
+        let _guard = Priority(1).attach();
+
+        let (res, guard) = Priority(2).attach().with_scope(Priority::current);
+        assert_eq!(res, Some(Priority(2)));
+        assert_eq!(Priority::current(), Some(Priority(2)));
+        drop(guard);
+        assert_eq!(Priority::current(), Some(Priority(1)));
+
+        let res = std::panic::catch_unwind(|| {
+            let (_, _guard) = Priority(3).attach().with_scope(|| panic!("oops"));
+        });
+        assert!(res.is_err());
+        assert_eq!(Priority::current(), Some(Priority(1)));
+        assert_eq!(Priority::nesting_depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn get_across_spawn() {
+        let x = Deadline::after_secs(1);
+
+        assert_eq!(
+            tokio::spawn(async { Deadline::current() }.with(x.clone()))
+                .await
+                .unwrap(),
+            Some(x)
+        )
+    }
+
+    #[tokio::test]
+    async fn boxed_future() {
+        let fut: Pin<Box<dyn Future<Output = Option<Priority>> + Send>> =
+            Box::pin(async { Priority::current() });
+
+        assert_eq!(fut.with(Priority(1)).await, Some(Priority(1)));
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Route {
+        method: &'static str,
+        path: String,
+    }
+
+    struct RouteRef<'a> {
+        path: &'a str,
+    }
+
+    impl<'a> From<&'a Route> for RouteRef<'a> {
+        fn from(value: &'a Route) -> Self {
+            Self { path: &value.path }
+        }
+    }
+
+    context_with_projection!(RouteRef<'_>, Route, ROUTE_CONTEXT);
+
+    #[test]
+    fn projection() {
+        assert!(RouteRef::borrow_current(|x| x.is_none()));
+
+        let route = Route {
+            method: "GET",
+            path: "/items".into(),
+        };
+        let _guard = route.clone().attach();
+
+        assert_eq!(
+            RouteRef::borrow_current(|x| x.map(|x| x.path.to_owned())),
+            Some(route.path.clone())
+        );
+        assert_eq!(RouteRef::current_owned().map(|x| x.method), Some("GET"));
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Label(String);
+
+    struct LabelRef<'a>(&'a str);
+
+    impl From<LabelRef<'_>> for Label {
+        fn from(value: LabelRef<'_>) -> Self {
+            Self(value.0.to_owned())
+        }
+    }
+
+    impl<'a> From<&'a Label> for LabelRef<'a> {
+        fn from(value: &'a Label) -> Self {
+            Self(&value.0)
+        }
+    }
+
+    context_cell_ref!(LabelRef<'_>, Label, LABEL_CONTEXT);
+
+    #[test]
+    fn cell_ref() {
+        let name = String::from("db");
+
+        LabelRef(&name).scoped(|| {
+            assert_eq!(Label::current(), Some(Label("db".into())));
+        });
+        assert_eq!(Label::current(), None);
+
+        let _guard = LabelRef(&name[1..]).attach();
+        assert_eq!(LabelRef::borrow_current(|x| x.map(|x| x.0.len())), Some(1));
+        assert_eq!(LabelRef::current_owned(), Some(Label("b".into())));
+    }
+
+    context_enum! {
+        #[derive(Debug, PartialEq)]
+        enum Verbosity {
+            Quiet,
+            #[default]
+            Normal,
+            Verbose,
+        }, VERBOSITY_CONTEXT;
+    }
+
+    #[test]
+    fn enum_context() {
+        assert_eq!(Verbosity::current(), None);
+        assert_eq!(Verbosity::current_or_default(), Verbosity::Normal);
+
+        let _outer = Verbosity::Quiet.attach();
+        Verbosity::Verbose.scoped(|| {
+            assert_eq!(Verbosity::current(), Some(Verbosity::Verbose));
+            assert_eq!(
+                Verbosity::with_display(|x| x.to_string()).as_deref(),
+                Some("Verbose")
+            );
+        });
+
+        assert_eq!(Verbosity::current(), Some(Verbosity::Quiet));
+    }
+
+    context_with_builder! {
+        #[derive(Debug, PartialEq)]
+        struct Caller {
+            service: String,
+            /// Attempt number.
+            attempt: u32,
+        }, builder = CallerBuilder, CALLER_CONTEXT;
+    }
+
+    #[test]
+    fn context_with_builder() {
+        let caller = CallerBuilder::new().service("api").attempt(2u8).build();
+        assert_eq!(
+            caller,
+            Caller {
+                service: "api".into(),
+                attempt: 2
+            }
+        );
+
+        let _guard = caller.attach();
+        assert_eq!(Caller::current_map(|x| x.attempt), Some(2));
+        assert_eq!(Caller::builder().attempt(1u32).try_build(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "field `attempt` of `Caller` is not set")]
+    fn context_with_builder_missing_field() {
+        Caller::builder().service("api").build();
+    }
+
+    #[tokio::test]
+    async fn propagate() {
+        let _priority_guard = Priority(1).attach();
+        let _timeout_guard = Timeout(Duration::from_secs(1)).attach();
+
+        let fut: super::WithContext<_, Priority> =
+            crate::propagate!(async { Priority::current() }; Priority);
+        assert_eq!(tokio::spawn(fut).await.unwrap(), Some(Priority(1)));
+
+        let fut = crate::propagate!(
+            async { (Priority::current(), Timeout::current()) };
+            Priority,
+            Timeout,
+        );
+        assert_eq!(
+            tokio::spawn(fut).await.unwrap(),
+            (Some(Priority(1)), Some(Timeout(Duration::from_secs(1))))
+        );
+    }
+
+    #[tokio::test]
+    async fn scope() {
+        let fut = Priority(1).scope(async {
+            tokio::task::yield_now().await;
+            Priority::current()
+        });
+
+        assert_eq!(tokio::spawn(fut).await.unwrap(), Some(Priority(1)));
+        assert_eq!(Priority::current(), None);
+    }
+
+    #[tokio::test]
+    async fn into_inner() {
+        let fut = async { Priority::current() }.with(Priority(1));
+        assert_eq!(fut.value(), Some(&Priority(1)));
+
+        let (fut, value) = fut.into_inner();
+        assert_eq!(value, Some(Priority(1)));
+        assert_eq!(fut.with(Priority(2)).await, Some(Priority(2)));
+
+        let mut fut = Box::pin(std::future::pending::<()>().with(Priority(3)));
+        assert!(futures::poll!(&mut fut).is_pending());
+        assert_eq!(fut.into_inner_boxed().1, Some(Priority(3)));
+
+        // value is dropped on completion
+        let mut fut = Box::pin(std::future::ready(1).with(Priority(4)));
+        assert_eq!((&mut fut).await, 1);
+        assert_eq!(fut.into_inner_boxed().1, None);
+    }
+
+    #[tokio::test]
+    async fn with_default() {
+        let current = || async { Priority::current() }.with_default(Priority(0));
+
+        assert_eq!(current().await, Some(Priority(0)));
+        assert_eq!(current().with(Priority(1)).await, Some(Priority(1)));
+    }
+
+    #[tokio::test]
+    async fn update_inside_future() {
+        let fut = async {
+            Priority::update(|x| x.0 += 1);
+            Priority::current()
+        };
+
+        assert_eq!(fut.with(Priority(1)).await, Some(Priority(2)));
+    }
+
+    #[tokio::test]
+    async fn map_context() {
+        let _guard = Priority(1).attach();
+
+        let fut =
+            async { Tagged::<u32>::current() }.map_context(|x: Priority| Tagged(u32::from(x.0)));
+        let lazy_fut = async { Tagged::<u32>::current() }
+            .map_context_with_current(|x: Priority| Tagged(u32::from(x.0) + 1));
+        drop(_guard);
+
+        assert_eq!(tokio::spawn(fut).await.unwrap(), Some(Tagged(1)));
+        assert_eq!(lazy_fut.await, None);
+
+        let lazy_fut = async { Tagged::<u32>::current() }
+            .map_context_with_current(|x: Priority| Tagged(u32::from(x.0) + 1));
+        assert_eq!(lazy_fut.with(Priority(2)).await, Some(Tagged(3)));
+    }
+
+    #[tokio::test]
+    async fn with_fn() {
+        let calls = std::cell::Cell::new(0);
+        let fut = async {
+            tokio::task::yield_now().await;
+            Priority::current()
+        }
+        .with_fn(|| {
+            calls.set(calls.get() + 1);
+            Priority(1)
+        });
+        assert_eq!(calls.get(), 0);
+
+        assert_eq!(fut.await, Some(Priority(1)));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn with2() {
+        let x = Deadline::after_secs(1);
+        let fut = async {
+            tokio::task::yield_now().await;
+            (Deadline::current(), Priority::current())
+        };
+
+        assert_eq!(
+            fut.with2(x.clone(), Priority(1)).await,
+            (Some(x), Some(Priority(1)))
+        );
+        assert_eq!(Deadline::current(), None);
+        assert_eq!(Priority::current(), None);
+    }
+
+    #[tokio::test]
+    async fn with_current2() {
+        let guard = Priority(1).attach();
+
+        let fut = async { (Deadline::current(), Priority::current()) }
+            .with_current2::<Deadline, Priority>();
+        drop(guard);
+
+        assert_eq!(fut.await, (None, Some(Priority(1))));
+    }
+
+    #[cfg(feature = "futures")]
+    #[tokio::test]
+    async fn fused_select() {
+        use futures::{
+            future::{FusedFuture, FutureExt as _},
+            pin_mut, select,
+        };
+
+        let first = async {
+            tokio::task::yield_now().await;
+            Priority::current()
+        }
+        .fuse()
+        .with(Priority(1));
+        let second = async { Priority::current() }.fuse().with(Priority(2));
+        pin_mut!(first, second);
+
+        let mut results = Vec::new();
+        loop {
+            select! {
+                x = first => results.push(x),
+                x = second => results.push(x),
+                complete => break,
+            }
+        }
+
+        assert_eq!(results, vec![Some(Priority(2)), Some(Priority(1))]);
+        assert!(first.is_terminated() && second.is_terminated());
+    }
+
+    #[test]
+    fn debug_without_debug_future() {
+        let fut = async {}.with(Priority(1));
+        assert_eq!(
+            format!("{fut:?}"),
+            "WithContext { inner: <Future>, value: Some(Priority(1)) }"
+        );
+
+        let fut = async {}.with_fn(|| Priority(1));
+        assert_eq!(
+            format!("{fut:?}"),
+            "WithContextFn { inner: <Future>, initialized: false, value: None }"
+        );
+    }
+
+    #[tokio::test]
+    async fn without() {
+        let _guard = Priority(1).attach();
+
+        assert_eq!(
+            async { Priority::current() }.without::<Priority>().await,
+            None
+        );
+        assert_eq!(Priority::current(), Some(Priority(1)));
+    }
+
+    #[tokio::test]
+    async fn owned_guard() {
+        let x = Deadline::after_secs(1);
+
+        let fut = async { Deadline::current() }.with_context_guard(x.clone().attach());
+        assert_eq!(Deadline::current(), Some(x.clone()));
+        assert_eq!(fut.await, Some(x));
+        assert_eq!(Deadline::current(), None);
     }
 }
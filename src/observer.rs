@@ -0,0 +1,232 @@
+//! Hooks, called when context values are attached and detached.
+
+use std::{
+    any::{Any, TypeId},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use crate::TypedContext;
+
+/// Observer of attaching and detaching values of some context.
+///
+/// Observer is called whenever value is attached or restored by any guard, including values, set on
+/// each poll of a future (like with [`FutureExt::with`](crate::FutureExt::with)), so it should be
+/// cheap. Each `on_attach` call is matched by exactly one `on_detach` call. Observer is called outside
+/// of TLS access, so it can read current values of any contexts.
+///
+/// Registered with [`TypedContext::set_observer`].
+pub trait ContextObserver<T>: Send + Sync {
+    /// Called after value is attached, with value, which was current before.
+    fn on_attach(&self, value: &T, previous: Option<&T>);
+
+    /// Called before value is detached, with value, which will be restored.
+    fn on_detach(&self, value: &T, next: Option<&T>);
+}
+
+/// Observer, emitting `tracing` events when values are attached and detached.
+///
+/// Events are emitted at `TRACE` level with `tyco` target and contain name of the context type and
+/// [`Debug`](std::fmt::Debug) representations of values, which helps to debug context propagation.
+/// Observer can be registered for any number of context types.
+///
+/// Requires `tracing` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context, TracingObserver, TypedContext};
+///
+/// #[derive(Clone, Debug)]
+/// struct TraceId(String);
+///
+/// context!(TraceId);
+///
+/// assert!(TraceId::set_observer(Box::new(TracingObserver)).is_ok());
+///
+/// // emits "context attached" event
+/// let _guard = TraceId("abc".into()).attach();
+/// ```
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingObserver;
+
+#[cfg(feature = "tracing")]
+impl<T: std::fmt::Debug> ContextObserver<T> for TracingObserver {
+    fn on_attach(&self, value: &T, previous: Option<&T>) {
+        tracing::trace!(
+            target: "tyco",
+            context = std::any::type_name::<T>(),
+            ?value,
+            ?previous,
+            "context attached"
+        );
+    }
+
+    fn on_detach(&self, value: &T, next: Option<&T>) {
+        tracing::trace!(
+            target: "tyco",
+            context = std::any::type_name::<T>(),
+            ?value,
+            ?next,
+            "context detached"
+        );
+    }
+}
+
+type ErasedObserver = &'static (dyn Any + Send + Sync);
+
+/// Fast path for contexts without observers, which is the most common case.
+static HAS_OBSERVERS: AtomicBool = AtomicBool::new(false);
+static OBSERVERS: RwLock<Vec<(TypeId, ErasedObserver)>> = RwLock::new(Vec::new());
+
+/// Register observer for context. Observer is set only once, following calls return error.
+pub(crate) fn set_observer<T: TypedContext>(
+    observer: Box<dyn ContextObserver<T>>,
+) -> Result<(), Box<dyn ContextObserver<T>>> {
+    let mut observers = OBSERVERS.write().unwrap_or_else(|e| e.into_inner());
+    let type_id = TypeId::of::<T>();
+
+    if observers.iter().any(|(x, _)| *x == type_id) {
+        return Err(observer);
+    }
+
+    let observer: ErasedObserver = Box::leak(Box::new(observer));
+    observers.push((type_id, observer));
+    HAS_OBSERVERS.store(true, Ordering::Release);
+
+    Ok(())
+}
+
+/// Get observer of context, if any.
+pub(crate) fn observer<T: TypedContext>() -> Option<&'static dyn ContextObserver<T>> {
+    if !HAS_OBSERVERS.load(Ordering::Acquire) {
+        return None;
+    }
+
+    let type_id = TypeId::of::<T>();
+    OBSERVERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(x, _)| *x == type_id)
+        .and_then(|(_, observer)| observer.downcast_ref::<Box<dyn ContextObserver<T>>>())
+        .map(|observer| observer.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::ContextObserver;
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tenant(&'static str);
+
+    crate::context!(Tenant);
+
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ContextObserver<Tenant> for &'static Recorder {
+        fn on_attach(&self, value: &Tenant, previous: Option<&Tenant>) {
+            let event = format!("attach {} over {:?}", value.0, previous.map(|x| x.0));
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_detach(&self, value: &Tenant, next: Option<&Tenant>) {
+            let event = format!("detach {} to {:?}", value.0, next.map(|x| x.0));
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn observe_attach_and_detach() {
+        static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+        assert!(Tenant::set_observer(Box::new(&RECORDER)).is_ok());
+        assert!(Tenant::set_observer(Box::new(&RECORDER)).is_err());
+
+        let outer = Tenant("outer").attach();
+        Tenant("inner").scoped(|| {});
+        futures::executor::block_on(async {}.with(Tenant("future")));
+        Tenant("value").attach().into_value();
+        drop(outer);
+
+        assert_eq!(
+            *RECORDER.0.lock().unwrap(),
+            [
+                "attach outer over None",
+                "attach inner over Some(\"outer\")",
+                "detach inner to Some(\"outer\")",
+                "attach future over Some(\"outer\")",
+                "detach future to Some(\"outer\")",
+                "attach value over Some(\"outer\")",
+                "detach value to Some(\"outer\")",
+                "detach outer to None",
+            ]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_observer() {
+        use std::{io, sync::Arc};
+
+        #[derive(Clone, Debug)]
+        enum Region {
+            Eu,
+            Us,
+        }
+
+        crate::context!(Region, REGION_CONTEXT);
+
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer({
+                let buffer = buffer.clone();
+                move || buffer.clone()
+            })
+            .with_ansi(false)
+            .without_time()
+            .with_target(false)
+            .finish();
+
+        assert!(Region::set_observer(Box::new(super::TracingObserver)).is_ok());
+        tracing::subscriber::with_default(subscriber, || {
+            let _outer = Region::Eu.attach();
+            Region::Us.scoped(|| {});
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events = output
+            .lines()
+            .map(|x| x.split_once("context").unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                r#" attached context="tyco::observer::tests::tracing_observer::Region" value=Eu previous=None"#,
+                r#" attached context="tyco::observer::tests::tracing_observer::Region" value=Us previous=Some(Eu)"#,
+                r#" detached context="tyco::observer::tests::tracing_observer::Region" value=Us next=Some(Eu)"#,
+                r#" detached context="tyco::observer::tests::tracing_observer::Region" value=Eu next=None"#,
+            ]
+        );
+    }
+}
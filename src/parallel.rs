@@ -0,0 +1,251 @@
+//! Context propagation into [`rayon`] thread pool.
+
+use rayon::iter::{
+    plumbing::{Consumer, Folder, UnindexedConsumer},
+    ParallelIterator,
+};
+
+use crate::TypedContext;
+
+/// Set value as context on all threads of the current rayon thread pool.
+///
+/// Value stays set until it is replaced, so it is useful for setting contexts, which are the same for
+/// all tasks in the pool. To set context on custom pool call this function inside
+/// [`ThreadPool::install`](rayon::ThreadPool::install).
+///
+/// Requires `rayon` feature.
+pub fn install_context<T: TypedContext + Send + Sync>(value: T) {
+    rayon::broadcast(|_| {
+        T::replace(value.clone());
+    });
+}
+
+/// Wrapper for a parallel iterator, responsible for managing its context.
+///
+/// Context is attached while items are processed by adapters, chained **after** this wrapper.
+#[derive(Clone, Debug)]
+pub struct WithContextParIter<I, T> {
+    inner: I,
+    value: Option<T>,
+}
+
+impl<I, T> ParallelIterator for WithContextParIter<I, T>
+where
+    I: ParallelIterator,
+    T: TypedContext + Send + Sync,
+{
+    type Item = I::Item;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        if let Some(ref value) = self.value {
+            self.inner.drive_unindexed(ContextConsumer {
+                inner: consumer,
+                value,
+            })
+        } else {
+            self.inner.drive_unindexed(consumer)
+        }
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        self.inner.opt_len()
+    }
+}
+
+struct ContextConsumer<'a, C, T> {
+    inner: C,
+    value: &'a T,
+}
+
+impl<'a, C, T, Item> Consumer<Item> for ContextConsumer<'a, C, T>
+where
+    C: Consumer<Item>,
+    T: TypedContext + Sync,
+{
+    type Folder = ContextFolder<'a, C::Folder, T>;
+    type Reducer = C::Reducer;
+    type Result = C::Result;
+
+    fn split_at(self, index: usize) -> (Self, Self, Self::Reducer) {
+        let (left, right, reducer) = self.inner.split_at(index);
+        (
+            ContextConsumer {
+                inner: left,
+                value: self.value,
+            },
+            ContextConsumer {
+                inner: right,
+                value: self.value,
+            },
+            reducer,
+        )
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        ContextFolder {
+            inner: self.inner.into_folder(),
+            value: self.value,
+        }
+    }
+
+    fn full(&self) -> bool {
+        self.inner.full()
+    }
+}
+
+impl<C, T, Item> UnindexedConsumer<Item> for ContextConsumer<'_, C, T>
+where
+    C: UnindexedConsumer<Item>,
+    T: TypedContext + Sync,
+{
+    fn split_off_left(&self) -> Self {
+        ContextConsumer {
+            inner: self.inner.split_off_left(),
+            value: self.value,
+        }
+    }
+
+    fn to_reducer(&self) -> Self::Reducer {
+        self.inner.to_reducer()
+    }
+}
+
+struct ContextFolder<'a, F, T> {
+    inner: F,
+    value: &'a T,
+}
+
+impl<F, T, Item> Folder<Item> for ContextFolder<'_, F, T>
+where
+    F: Folder<Item>,
+    T: TypedContext,
+{
+    type Result = F::Result;
+
+    fn consume(self, item: Item) -> Self {
+        let _guard = unsafe { self.value.attach_ref() };
+        ContextFolder {
+            inner: self.inner.consume(item),
+            value: self.value,
+        }
+    }
+
+    fn consume_iter<I>(self, iter: I) -> Self
+    where
+        I: IntoIterator<Item = Item>,
+    {
+        let _guard = unsafe { self.value.attach_ref() };
+        ContextFolder {
+            inner: self.inner.consume_iter(iter),
+            value: self.value,
+        }
+    }
+
+    fn complete(self) -> Self::Result {
+        let _guard = unsafe { self.value.attach_ref() };
+        self.inner.complete()
+    }
+
+    fn full(&self) -> bool {
+        self.inner.full()
+    }
+}
+
+/// Extension trait allowing to attach context to parallel iterators.
+///
+/// Requires `rayon` feature.
+///
+/// # Example:
+///
+/// ```
+/// use rayon::prelude::*;
+/// use tyco::{context, ParallelIteratorExt, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct RequestId(u64);
+///
+/// context!(RequestId);
+///
+/// let ids = (0..10)
+///     .into_par_iter()
+///     .with(RequestId(1))
+///     .map(|_| RequestId::current())
+///     .collect::<Vec<_>>();
+///
+/// assert!(ids.iter().all(|x| x == &Some(RequestId(1))));
+/// ```
+pub trait ParallelIteratorExt: ParallelIterator {
+    /// Set value as context for parallel iterator.
+    fn with<T>(self, value: T) -> WithContextParIter<Self, T> {
+        WithContextParIter {
+            inner: self,
+            value: Some(value),
+        }
+    }
+
+    /// Set optional value as context for parallel iterator.
+    fn with_opt<T>(self, value: Option<T>) -> WithContextParIter<Self, T> {
+        WithContextParIter { inner: self, value }
+    }
+
+    /// Take current context and set is as context for a parallel iterator.
+    ///
+    /// Basically it is `self.with_opt(T::current())`.
+    fn with_current<T: TypedContext>(self) -> WithContextParIter<Self, T> {
+        self.with_opt(T::current())
+    }
+}
+
+impl<I: ParallelIterator> ParallelIteratorExt for I {}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::{install_context, ParallelIteratorExt};
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tag(u32);
+
+    crate::context!(Tag);
+
+    fn pool() -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn par_iter() {
+        let tags = pool().install(|| {
+            (0..1000)
+                .into_par_iter()
+                .with(Tag(1))
+                .filter(|_| Tag::current().is_some())
+                .map(|_| Tag::current())
+                .collect::<Vec<_>>()
+        });
+
+        assert_eq!(tags, vec![Some(Tag(1)); 1000]);
+    }
+
+    #[test]
+    fn install() {
+        let pool = pool();
+        pool.install(|| install_context(Tag(2)));
+
+        let tags = pool.install(|| {
+            (0..1000)
+                .into_par_iter()
+                .map(|_| Tag::current())
+                .collect::<Vec<_>>()
+        });
+
+        assert_eq!(tags, vec![Some(Tag(2)); 1000]);
+    }
+}
@@ -0,0 +1,154 @@
+//! Contexts with value, shared and modified by all tasks it is propagated to.
+
+use std::{fmt, ops::Deref, sync::Arc};
+
+use tokio::sync::RwLock;
+
+use crate::{ContextGuard, TagBackend, TypedContext};
+
+/// Context, holding value behind shared [`RwLock`].
+///
+/// Unlike regular contexts, where each task gets its own copy of the value, all tasks, which got
+/// the context (for example, with [`FutureExt::with_current`](crate::FutureExt::with_current)),
+/// hold the same lock, so modification, made by one of them, is visible to all others. Each value
+/// type has its own slot, so `SharedContext<T>` doesn't require any macros.
+///
+/// Requires `tokio` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{FutureExt, SharedContext, TypedContext};
+///
+/// #[derive(Debug, Default)]
+/// struct Cancelled(bool);
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let _guard = SharedContext::new(Cancelled::default()).attach();
+///
+/// tokio::spawn(
+///     async {
+///         let shared = SharedContext::<Cancelled>::current_shared().unwrap();
+///         shared.write().await.0 = true;
+///     }
+///     .with_current::<SharedContext<Cancelled>>(),
+/// )
+/// .await
+/// .unwrap();
+///
+/// let shared = SharedContext::<Cancelled>::current().unwrap();
+/// assert!(shared.read().await.0);
+/// # });
+/// ```
+pub struct SharedContext<T>(Arc<RwLock<T>>);
+
+/// Guard, created with [`SharedContext::attach_shared`].
+///
+/// Requires `tokio` feature.
+pub type SharedContextGuard<T> = ContextGuard<SharedContext<T>>;
+
+impl<T: Send + Sync + 'static> SharedContext<T> {
+    /// Create context with new lock, holding value.
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(value)))
+    }
+
+    /// Get lock of the current context.
+    pub fn current_shared() -> Option<Arc<RwLock<T>>> {
+        Self::current_map(|x| x.0.clone())
+    }
+
+    /// Set existing lock as current context.
+    pub fn attach_shared(value: Arc<RwLock<T>>) -> SharedContextGuard<T> {
+        Self(value).attach()
+    }
+
+    /// Get the lock.
+    pub fn into_inner(self) -> Arc<RwLock<T>> {
+        self.0
+    }
+}
+
+impl<T> Clone for SharedContext<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Deref for SharedContext<T> {
+    type Target = RwLock<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> From<Arc<RwLock<T>>> for SharedContext<T> {
+    fn from(value: Arc<RwLock<T>>) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedContext<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedContext").field(&self.0).finish()
+    }
+}
+
+impl<T: Send + Sync + 'static> TypedContext for SharedContext<T> {
+    type Backend = TagBackend;
+
+    fn tls() -> &'static Self::Backend {
+        TagBackend::get()
+    }
+
+    crate::__context_counters!();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::SharedContext;
+    use crate::{FutureExt, TypedContext};
+
+    #[tokio::test]
+    async fn shared_between_tasks() {
+        let _guard = SharedContext::new(0u32).attach();
+
+        let tasks = (0..4)
+            .map(|_| {
+                tokio::spawn(
+                    async {
+                        let shared = SharedContext::<u32>::current_shared().unwrap();
+                        *shared.write().await += 1;
+                    }
+                    .with_current::<SharedContext<u32>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let shared = SharedContext::<u32>::current().unwrap();
+        assert_eq!(*shared.read().await, 4);
+    }
+
+    #[tokio::test]
+    async fn attach_shared() {
+        let lock = Arc::new(tokio::sync::RwLock::new(String::from("a")));
+        let guard = SharedContext::attach_shared(lock.clone());
+
+        SharedContext::<String>::current_shared()
+            .unwrap()
+            .write()
+            .await
+            .push('b');
+        drop(guard);
+
+        assert_eq!(*lock.read().await, "ab");
+        assert!(SharedContext::<String>::current().is_none());
+    }
+}
@@ -0,0 +1,132 @@
+//! Context propagation for [`Sink`]s.
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_sink::Sink;
+
+use crate::{TypedContext, WithContext};
+
+impl<S: Sink<Item>, T: TypedContext, Item> Sink<Item> for WithContext<S, T> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+
+        let _guard = this.value.as_ref().map(|x| unsafe { x.attach_ref() });
+        this.inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        let this = self.project();
+
+        let _guard = this.value.as_ref().map(|x| unsafe { x.attach_ref() });
+        this.inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+
+        let _guard = this.value.as_ref().map(|x| unsafe { x.attach_ref() });
+        this.inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+
+        let _guard = this.value.as_ref().map(|x| unsafe { x.attach_ref() });
+        this.inner.poll_close(cx)
+    }
+}
+
+/// Extension trait allowing to attach context to sinks.
+///
+/// Requires `futures` feature.
+pub trait SinkExt<Item>: Sink<Item> + Sized {
+    /// Set value as context for all methods of the sink.
+    fn with_context_sink<T: TypedContext>(self, value: T) -> WithContext<Self, T> {
+        WithContext {
+            inner: self,
+            value: Some(value),
+        }
+    }
+
+    /// Take current context and set is as context for a sink.
+    fn with_current_sink<T: TypedContext>(self) -> WithContext<Self, T> {
+        WithContext {
+            inner: self,
+            value: T::current(),
+        }
+    }
+}
+
+impl<S: Sink<Item>, Item> SinkExt<Item> for S {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use futures::{Sink, SinkExt as _};
+
+    use super::SinkExt;
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tag(u32);
+
+    crate::context!(Tag);
+
+    /// Sink, recording context, seen by each of its methods.
+    #[derive(Default)]
+    struct Recorder(Vec<(&'static str, Option<Tag>)>);
+
+    impl Sink<u32> for Recorder {
+        type Error = Infallible;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            self.get_mut().0.push(("ready", Tag::current()));
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, _: u32) -> Result<(), Infallible> {
+            self.get_mut().0.push(("send", Tag::current()));
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            self.get_mut().0.push(("flush", Tag::current()));
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            self.get_mut().0.push(("close", Tag::current()));
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn all_methods() {
+        let mut sink = Recorder::default().with_context_sink(Tag(1));
+
+        sink.send(1).await.unwrap();
+        sink.close().await.unwrap();
+
+        let (recorder, _) = sink.into_inner();
+        assert_eq!(
+            recorder.0,
+            [
+                ("ready", Some(Tag(1))),
+                ("send", Some(Tag(1))),
+                ("flush", Some(Tag(1))),
+                ("close", Some(Tag(1))),
+            ]
+        );
+        assert_eq!(Tag::current(), None);
+    }
+}
@@ -0,0 +1,297 @@
+//! Type-erased snapshots of all registered contexts.
+
+use std::{
+    any::{Any, TypeId},
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+
+use crate::TypedContext;
+
+mod sealed {
+    use std::any::Any;
+
+    /// Captured value of some context, which can be attached any number of times.
+    pub trait ErasedAttach: Send {
+        /// Attach clone of the value, returning type-erased guard.
+        fn attach_erased(&self) -> Box<dyn Any>;
+
+        /// Attach reference to the value for the duration of the closure call.
+        fn scoped_erased(&self, f: &mut dyn FnMut());
+    }
+}
+
+use sealed::ErasedAttach;
+
+impl<T: TypedContext + Send> ErasedAttach for T {
+    fn attach_erased(&self) -> Box<dyn Any> {
+        Box::new(self.clone().attach())
+    }
+
+    fn scoped_erased(&self, f: &mut dyn FnMut()) {
+        // Value is borrowed only for the duration of the call and guard is dropped before return.
+        let _guard = unsafe { self.attach_ref() };
+        f()
+    }
+}
+
+/// Attach references to all values, one after another, and call function with all of them set.
+///
+/// Guards live on the stack, so nothing is allocated, and previous values are restored in reverse
+/// order, even if `f` panics.
+fn scoped_all(values: &[Box<dyn ErasedAttach>], f: &mut dyn FnMut()) {
+    match values.split_first() {
+        Some((first, rest)) => first.scoped_erased(&mut || scoped_all(rest, f)),
+        None => f(),
+    }
+}
+
+struct Registration {
+    type_id: TypeId,
+    capture: fn() -> Option<Box<dyn ErasedAttach>>,
+}
+
+static REGISTRY: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
+fn capture<T: TypedContext + Send>() -> Option<Box<dyn ErasedAttach>> {
+    T::current().map(|x| Box::new(x) as Box<dyn ErasedAttach>)
+}
+
+/// Snapshot of current values of all registered contexts.
+///
+/// Context types should be registered with [`ContextSnapshot::register`] before capturing snapshot.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context, ContextSnapshot, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct RequestId(u64);
+///
+/// context!(RequestId);
+///
+/// ContextSnapshot::register::<RequestId>();
+///
+/// let snapshot = RequestId(1).scoped(ContextSnapshot::capture);
+///
+/// std::thread::spawn(move || {
+///     let _guard = snapshot.restore();
+///     assert_eq!(RequestId::current(), Some(RequestId(1)));
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+pub struct ContextSnapshot {
+    values: Vec<Box<dyn ErasedAttach>>,
+}
+
+impl ContextSnapshot {
+    /// Register context type, so it is included in snapshots.
+    ///
+    /// Registering same type several times has no effect.
+    pub fn register<T: TypedContext + Send>() {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        let type_id = TypeId::of::<T>();
+
+        if !registry.iter().any(|x| x.type_id == type_id) {
+            registry.push(Registration {
+                type_id,
+                capture: capture::<T>,
+            });
+        }
+    }
+
+    /// Capture current values of all registered contexts.
+    ///
+    /// Contexts without value are not included in snapshot.
+    pub fn capture() -> Self {
+        let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+
+        Self {
+            values: registry.iter().filter_map(|x| (x.capture)()).collect(),
+        }
+    }
+
+    /// Attach all captured values.
+    ///
+    /// Snapshot can be restored any number of times, values are cloned on each restore.
+    pub fn restore(&self) -> SnapshotGuard {
+        SnapshotGuard {
+            guards: self.values.iter().map(|x| x.attach_erased()).collect(),
+        }
+    }
+}
+
+/// Guard, created with [`ContextSnapshot::restore`], keeping all captured values as current contexts.
+///
+/// On drop it will restore previous values in reverse order.
+#[must_use = "SnapshotGuard must be held in a binding; dropping it immediately unsets the contexts"]
+pub struct SnapshotGuard {
+    guards: Vec<Box<dyn Any>>,
+}
+
+impl Drop for SnapshotGuard {
+    fn drop(&mut self) {
+        while let Some(guard) = self.guards.pop() {
+            drop(guard);
+        }
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, restoring captured snapshot of contexts while future is polled.
+    ///
+    /// Created with [`FutureExt::with_captured`](crate::FutureExt::with_captured). Only context types,
+    /// registered with [`ContextSnapshot::register`] before wrapper is created, are captured.
+    ///
+    /// Values are captured once, on creation of the wrapper, and on each poll references to them are
+    /// set as current contexts, same as [`WithContext`](crate::WithContext) does, so polls neither
+    /// clone values nor allocate.
+    pub struct WithCaptured<F> {
+        #[pin]
+        inner: F,
+        snapshot: ContextSnapshot,
+    }
+}
+
+impl<F> WithCaptured<F> {
+    pub(crate) fn new(inner: F) -> Self {
+        Self {
+            inner,
+            snapshot: ContextSnapshot::capture(),
+        }
+    }
+}
+
+impl<F: Future> Future for WithCaptured<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut inner = this.inner;
+        let mut output = Poll::Pending;
+
+        scoped_all(&this.snapshot.values, &mut || {
+            output = inner.as_mut().poll(cx)
+        });
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContextSnapshot;
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RequestId(u64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct UserId(u64);
+
+    crate::context!(RequestId, REQUEST_ID_CONTEXT);
+    crate::context!(UserId, USER_ID_CONTEXT);
+
+    #[test]
+    fn capture_and_restore() {
+        ContextSnapshot::register::<RequestId>();
+        ContextSnapshot::register::<UserId>();
+        ContextSnapshot::register::<UserId>();
+
+        let _request_guard = RequestId(1).attach();
+        let _user_guard = UserId(2).attach();
+        let snapshot = ContextSnapshot::capture();
+
+        let handle = std::thread::spawn(move || {
+            let guard = snapshot.restore();
+            let restored = (RequestId::current(), UserId::current());
+            drop(guard);
+
+            (restored, (RequestId::current(), UserId::current()))
+        });
+
+        assert_eq!(
+            handle.join().unwrap(),
+            ((Some(RequestId(1)), Some(UserId(2))), (None, None))
+        );
+    }
+
+    #[tokio::test]
+    async fn with_captured() {
+        ContextSnapshot::register::<RequestId>();
+        ContextSnapshot::register::<UserId>();
+
+        let guard = (RequestId(3).attach(), UserId(4).attach());
+        let fut = async {
+            tokio::task::yield_now().await;
+            (RequestId::current(), UserId::current())
+        }
+        .with_captured();
+        drop(guard);
+
+        assert_eq!(
+            tokio::spawn(fut).await.unwrap(),
+            (Some(RequestId(3)), Some(UserId(4)))
+        );
+        assert_eq!(RequestId::current(), None);
+    }
+
+    #[test]
+    fn with_captured_polls_dont_clone() {
+        use std::{
+            future::Future,
+            sync::atomic::{AtomicUsize, Ordering},
+            task::{Context, Poll},
+        };
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq)]
+        struct Counted(u64);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Self(self.0)
+            }
+        }
+
+        crate::context!(Counted);
+        ContextSnapshot::register::<Counted>();
+        ContextSnapshot::register::<RequestId>();
+
+        let guard = (Counted(1).attach(), RequestId(5).attach());
+        let mut polls = 0;
+        let mut fut = Box::pin(
+            std::future::poll_fn(|cx| {
+                polls += 1;
+                assert!(Counted::with_current_ref(|x| x == Some(&Counted(1))));
+                assert_eq!(RequestId::current(), Some(RequestId(5)));
+                if polls < 3 {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            })
+            .with_captured(),
+        );
+        drop(guard);
+        let clones = CLONES.load(Ordering::Relaxed);
+
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let _outer = RequestId(6).attach();
+        while fut.as_mut().poll(&mut cx).is_pending() {
+            assert!(!Counted::is_set());
+            assert_eq!(RequestId::current(), Some(RequestId(6)));
+        }
+
+        assert_eq!(CLONES.load(Ordering::Relaxed), clones);
+        assert_eq!(RequestId::current(), Some(RequestId(6)));
+    }
+}
@@ -0,0 +1,164 @@
+//! [`tracing`](https://docs.rs/tracing) integration, exposing current span as a context.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+use tracing::{span::Id, Span};
+
+use crate::{ContextGuard, TypedContext};
+
+/// Context, containing ID of the current span.
+///
+/// Allows code, which is not aware of `tracing`, to read ID of current span. Value is set with
+/// [`SpanContextExt::attach_as_context`] or [`FutureExt::with_span`](crate::FutureExt::with_span),
+/// entering span with `tracing` itself doesn't change it.
+///
+/// Requires `tracing` feature.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpanContext(pub Id);
+
+crate::context!(SpanContext);
+
+impl SpanContext {
+    fn of(span: &Span) -> Option<Self> {
+        span.id().map(Self)
+    }
+}
+
+/// Context, containing the current span itself.
+///
+/// Unlike `Span::enter` guard it can be freely moved between threads, so span can be propagated
+/// to spawned tasks and threads along with other contexts. Value is set with
+/// [`FutureExt::with_span`](crate::FutureExt::with_span) and
+/// [`FutureExt::with_current_span`](crate::FutureExt::with_current_span), or attached manually.
+///
+/// Requires `tracing` feature.
+#[derive(Clone, Debug)]
+pub struct TracingSpanContext(pub Span);
+
+crate::context!(TracingSpanContext, TRACING_SPAN_CONTEXT);
+
+/// Extension trait for [`Span`], allowing to set it as context.
+///
+/// Requires `tracing` feature.
+pub trait SpanContextExt {
+    /// Set ID of the span as current [`SpanContext`].
+    ///
+    /// Span itself is returned as is, it is not entered. Disabled spans have no ID, so `None` is
+    /// returned instead of the guard and current context is not changed.
+    fn attach_as_context(self) -> (Span, Option<ContextGuard<SpanContext>>);
+}
+
+impl SpanContextExt for Span {
+    fn attach_as_context(self) -> (Span, Option<ContextGuard<SpanContext>>) {
+        let guard = SpanContext::of(&self).map(TypedContext::attach);
+        (self, guard)
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, entering span and setting it as [`TracingSpanContext`] and its ID as
+    /// [`SpanContext`] while future is polled.
+    ///
+    /// Requires `tracing` feature.
+    #[derive(Clone, Debug)]
+    pub struct WithSpan<F> {
+        #[pin]
+        inner: F,
+        span: TracingSpanContext,
+        value: Option<SpanContext>,
+    }
+}
+
+impl<F> WithSpan<F> {
+    pub(crate) fn new(inner: F, span: Span) -> Self {
+        Self {
+            inner,
+            value: SpanContext::of(&span),
+            span: TracingSpanContext(span),
+        }
+    }
+}
+
+impl<F: Future> Future for WithSpan<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let _entered = this.span.0.enter();
+        let _span_guard = unsafe { this.span.attach_ref() };
+        let _guard = this.value.as_ref().map(|x| unsafe { x.attach_ref() });
+        this.inner.poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{info_span, Span};
+
+    use super::{SpanContext, SpanContextExt, TracingSpanContext};
+    use crate::{FutureExt, TypedContext};
+
+    #[test]
+    fn attach_as_context() {
+        tracing::subscriber::with_default(tracing_subscriber::registry(), || {
+            let (span, guard) = info_span!("request").attach_as_context();
+
+            assert!(guard.is_some());
+            assert_eq!(SpanContext::current(), span.id().map(SpanContext));
+
+            drop(guard);
+            assert_eq!(SpanContext::current(), None);
+        });
+    }
+
+    #[test]
+    fn disabled_span() {
+        let (_span, guard) = Span::none().attach_as_context();
+
+        assert!(guard.is_none());
+        assert_eq!(SpanContext::current(), None);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn with_span() {
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry());
+        let span = info_span!("request");
+
+        let (current_span, context) = async {
+            tokio::task::yield_now().await;
+            (Span::current().id(), SpanContext::current())
+        }
+        .with_span(span.clone())
+        .await;
+
+        assert_eq!(current_span, span.id());
+        assert_eq!(context, span.id().map(SpanContext));
+        assert_eq!(SpanContext::current(), None);
+    }
+
+    #[tokio::test]
+    async fn with_current_span() {
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry());
+        let span = info_span!("request");
+
+        let fut = span.in_scope(|| {
+            async {
+                tokio::task::yield_now().await;
+                (
+                    Span::current().id(),
+                    TracingSpanContext::current().and_then(|x| x.0.id()),
+                )
+            }
+            .with_current_span()
+        });
+
+        assert_eq!(tokio::spawn(fut).await.unwrap(), (span.id(), span.id()));
+        assert!(TracingSpanContext::current().is_none());
+    }
+}
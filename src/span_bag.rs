@@ -0,0 +1,236 @@
+//! Built-in context for accumulating key-value attributes of current execution.
+//!
+//! Requires `std` feature.
+//!
+//! # Example:
+//!
+//! ```
+//! use tyco::{
+//!     span_bag::{SpanBag, SpanValue},
+//!     TypedContext,
+//! };
+//!
+//! fn handle_request(user_id: i64) {
+//!     SpanBag::insert("user_id", user_id);
+//!     SpanBag::insert("cached", false);
+//! }
+//!
+//! SpanBag::new().with("request_id", "abc").scoped(|| {
+//!     handle_request(42);
+//!
+//!     assert_eq!(SpanBag::get("user_id"), Some(SpanValue::I64(42)));
+//!     assert_eq!(
+//!         SpanBag::with_current_ref(|x| x.map(ToString::to_string)).as_deref(),
+//!         Some("cached=false request_id=abc user_id=42")
+//!     );
+//! });
+//! ```
+
+use std::{
+    collections::{hash_map, HashMap},
+    fmt,
+};
+
+use crate::TypedContext;
+
+/// Value of the attribute in [`SpanBag`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpanValue {
+    /// String value.
+    String(String),
+    /// Integer value.
+    I64(i64),
+    /// Floating point value.
+    F64(f64),
+    /// Boolean value.
+    Bool(bool),
+}
+
+impl fmt::Display for SpanValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(x) => f.write_str(x),
+            Self::I64(x) => x.fmt(f),
+            Self::F64(x) => x.fmt(f),
+            Self::Bool(x) => x.fmt(f),
+        }
+    }
+}
+
+impl From<String> for SpanValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for SpanValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<i64> for SpanValue {
+    fn from(value: i64) -> Self {
+        Self::I64(value)
+    }
+}
+
+impl From<i32> for SpanValue {
+    fn from(value: i32) -> Self {
+        Self::I64(value.into())
+    }
+}
+
+impl From<u32> for SpanValue {
+    fn from(value: u32) -> Self {
+        Self::I64(value.into())
+    }
+}
+
+impl From<f64> for SpanValue {
+    fn from(value: f64) -> Self {
+        Self::F64(value)
+    }
+}
+
+impl From<bool> for SpanValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+/// Context, holding set of key-value attributes, which are accumulated during execution and read
+/// at the end (for example, to append them to a log line).
+///
+/// Static methods work with current bag: it should be attached at the start of execution (like
+/// `SpanBag::new().attach()`), otherwise attributes are discarded. Like any other context, bag is
+/// cloned when propagated into another task or thread, so attributes, inserted there, are not
+/// visible to the parent. Note that bag, set with [`FutureExt::with`](crate::FutureExt::with), is
+/// restored on each poll (see [`TypedContext::update`]), so inside of futures bag should be attached
+/// with [`TypedContext::attach`] to keep attributes between polls.
+///
+/// [`Display`](fmt::Display) implementation writes attributes as `key=value`, sorted by key.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpanBag {
+    values: HashMap<&'static str, SpanValue>,
+}
+
+crate::context!(SpanBag, SPAN_BAG_CONTEXT);
+
+impl SpanBag {
+    /// Create empty bag.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add attribute to the bag.
+    pub fn with(mut self, key: &'static str, value: impl Into<SpanValue>) -> Self {
+        self.values.insert(key, value.into());
+        self
+    }
+
+    /// Insert attribute into current bag, replacing previous value of the key.
+    ///
+    /// Does nothing if no bag is attached.
+    pub fn insert(key: &'static str, value: impl Into<SpanValue>) {
+        let value = value.into();
+        Self::update(|bag| {
+            bag.values.insert(key, value);
+        });
+    }
+
+    /// Get clone of the attribute of current bag.
+    pub fn get(key: &'static str) -> Option<SpanValue> {
+        Self::with_current_ref(|bag| bag.and_then(|bag| bag.values.get(key).cloned()))
+    }
+
+    /// Take all attributes out of current bag, leaving it empty.
+    ///
+    /// Returns empty map if no bag is attached.
+    pub fn drain() -> HashMap<&'static str, SpanValue> {
+        let mut values = HashMap::new();
+        Self::update(|bag| values = std::mem::take(&mut bag.values));
+        values
+    }
+
+    /// Get attributes of the bag.
+    pub fn values(&self) -> &HashMap<&'static str, SpanValue> {
+        &self.values
+    }
+
+    /// Convert into map of attributes.
+    pub fn into_inner(self) -> HashMap<&'static str, SpanValue> {
+        self.values
+    }
+}
+
+impl fmt::Display for SpanBag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut values = self.values.iter().collect::<Vec<_>>();
+        values.sort_unstable_by_key(|(key, _)| *key);
+
+        for (i, (key, value)) in values.into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{key}={value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a SpanBag {
+    type Item = (&'a &'static str, &'a SpanValue);
+    type IntoIter = hash_map::Iter<'a, &'static str, SpanValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{SpanBag, SpanValue};
+    use crate::{FutureExt, TypedContext};
+
+    #[test]
+    fn insert_and_drain() {
+        SpanBag::insert("ignored", true);
+        assert_eq!(SpanBag::get("ignored"), None);
+
+        let _guard = SpanBag::new().with("region", "eu").attach();
+        SpanBag::insert("attempt", 2);
+        SpanBag::insert("ratio", 0.5);
+        SpanBag::insert("attempt", 3);
+
+        assert_eq!(SpanBag::get("attempt"), Some(SpanValue::I64(3)));
+        assert_eq!(
+            SpanBag::drain(),
+            HashMap::from([
+                ("region", SpanValue::String("eu".into())),
+                ("attempt", SpanValue::I64(3)),
+                ("ratio", SpanValue::F64(0.5)),
+            ])
+        );
+        assert_eq!(SpanBag::current(), Some(SpanBag::new()));
+    }
+
+    #[tokio::test]
+    async fn propagation() {
+        let _guard = SpanBag::new().with("request_id", "abc").attach();
+
+        let child = tokio::spawn(
+            async {
+                SpanBag::insert("child", true);
+                SpanBag::current().unwrap().to_string()
+            }
+            .with_current::<SpanBag>(),
+        );
+
+        assert_eq!(child.await.unwrap(), "child=true request_id=abc");
+        assert_eq!(SpanBag::get("child"), None);
+    }
+}
@@ -0,0 +1,366 @@
+//! Stack-based contexts, which accumulate values instead of replacing them.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    thread::LocalKey,
+};
+
+use pin_project_lite::pin_project;
+
+use crate::{ContextGuard, ContextRefGuard, ContextValue, TypedContext};
+
+/// Trait for interaction with stacked contexts.
+///
+/// Unlike [`TypedContext`], which holds single value, stacked context keeps all pushed values, so
+/// things like nested spans or middleware stacks can be represented. Stacked context is a typed
+/// context as well: top of the stack is its current value, so all methods of [`TypedContext`]
+/// (like [`TypedContext::current`]) return the top of the stack. Values, attached directly with
+/// [`TypedContext::attach`], replace current value, but are not pushed on the stack.
+pub trait StackedContext: TypedContext {
+    /// Get storage of the stack.
+    ///
+    /// Implemented by [`context_stack!`](crate::context_stack) macro.
+    fn stack() -> &'static LocalKey<RefCell<Vec<ContextValue<Self>>>>;
+
+    /// Push value on top of the stack.
+    ///
+    /// Value will be removed from the stack when returned guard is dropped. Guard, created while
+    /// future, wrapped with [`WithContextStack`], is polled, shouldn't be returned out of the poll:
+    /// wrapper restores the stack to the size it had before the poll, so value of such guard is
+    /// removed at the end of the poll, and dropping the guard later leaves the stack untouched.
+    fn push(self) -> StackGuard<Self> {
+        let value = Arc::new(self);
+        let _ = Self::stack()
+            .try_with(|stack| stack.borrow_mut().push(ContextValue::Owned(value.clone())));
+
+        StackGuard {
+            guard: Self::attach_arc(value),
+        }
+    }
+
+    /// Push reference to a value on top of the stack.
+    ///
+    /// This function is mainly used for [`WithContextStack`] implementation and should
+    /// not be used by user (or used with great caution !!!).
+    ///
+    /// # Safety
+    ///
+    /// Incorrect usage might lead to segfault.
+    unsafe fn push_ref(&self) -> StackRefGuard<'_, Self> {
+        let static_ref: &'static Self = unsafe { &*(self as *const Self) };
+        let previous_len = Self::stack()
+            .try_with(|stack| {
+                let mut stack = stack.borrow_mut();
+                stack.push(ContextValue::Borrowed(static_ref));
+                stack.len() - 1
+            })
+            .ok();
+
+        StackRefGuard {
+            previous_len,
+            _guard: unsafe { self.attach_ref() },
+        }
+    }
+
+    /// Get clone of the top of the stack.
+    ///
+    /// `None` is returned if stack is empty.
+    fn current_owned() -> Option<Self> {
+        Self::stack().with(|stack| stack.borrow().last().map(|x| (**x).clone()))
+    }
+
+    /// Get clones of all values in the stack, from bottom to top.
+    fn all() -> Vec<Self> {
+        Self::stack().with(|stack| stack.borrow().iter().map(|x| (**x).clone()).collect())
+    }
+}
+
+/// Guard, created with [`StackedContext::push`], keeping value on the stack.
+///
+/// On drop it will remove exactly one value, pushed by this guard, from the stack (usually the top
+/// one, unless guards are dropped not in reverse order of creation) and restore current value of
+/// the context.
+#[must_use = "StackGuard must be held in a binding; dropping it immediately pops the value"]
+pub struct StackGuard<T: StackedContext> {
+    guard: ContextGuard<T>,
+}
+
+impl<T: StackedContext> Drop for StackGuard<T> {
+    fn drop(&mut self) {
+        let _ = T::stack().try_with(|stack| {
+            let mut stack = stack.borrow_mut();
+            let position = stack.iter().rposition(
+                |x| matches!(x, ContextValue::Owned(x) if Arc::ptr_eq(x, &self.guard.value)),
+            );
+            if let Some(position) = position {
+                stack.remove(position);
+            }
+        });
+    }
+}
+
+/// Guard, created with [`StackedContext::push_ref`], keeping value on the stack.
+///
+/// On drop it will restore stack to the size it had before push and restore current value of the
+/// context.
+#[must_use = "StackRefGuard must be held in a binding; dropping it immediately pops the value"]
+pub struct StackRefGuard<'a, T: StackedContext> {
+    previous_len: Option<usize>,
+    _guard: ContextRefGuard<'a, T>,
+}
+
+impl<T: StackedContext> Drop for StackRefGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(previous_len) = self.previous_len {
+            let _ = T::stack().try_with(|stack| stack.borrow_mut().truncate(previous_len));
+        }
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, pushing value on the context stack while future is polled.
+    ///
+    /// Value is pushed on each poll and stack is restored to the size it had before push when poll
+    /// returns, so from inside of the future value stays on top of the stack for whole lifetime of it.
+    /// Value is not kept on the stack between polls, otherwise it would be visible to other tasks,
+    /// running on the same thread, and would be left on the stack of the old thread if future is moved
+    /// to another one.
+    #[derive(Clone)]
+    pub struct WithContextStack<F, T> {
+        #[pin]
+        inner: F,
+        value: T,
+    }
+}
+
+impl<F, T> WithContextStack<F, T> {
+    pub(crate) fn new(inner: F, value: T) -> Self {
+        Self { inner, value }
+    }
+}
+
+impl<F, T: fmt::Debug> fmt::Debug for WithContextStack<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithContextStack")
+            .field("inner", &format_args!("<Future>"))
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+impl<F: Future, T: StackedContext> Future for WithContextStack<F, T> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let _guard = unsafe { this.value.push_ref() };
+        this.inner.poll(cx)
+    }
+}
+
+/// Macro for implementing stacked context.
+///
+/// This macro will generate implementation of [`StackedContext`] and [`TypedContext`](crate::TypedContext)
+/// traits for your type alongside with necessary TLS definitions. Macro accept single argument - path
+/// to type.
+///
+/// TLS variables are defined inside of anonymous `const _` block, so macro can be used any number of
+/// times in one module.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context_stack, StackedContext, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct Span(&'static str);
+///
+/// context_stack!(Span);
+///
+/// let _outer = Span("outer").push();
+/// let inner = Span("inner").push();
+/// assert_eq!(Span::all(), vec![Span("outer"), Span("inner")]);
+///
+/// assert_eq!(Span::current(), Some(Span("inner")));
+///
+/// drop(inner);
+/// assert_eq!(Span::current(), Some(Span("outer")));
+/// ```
+#[macro_export]
+macro_rules! context_stack {
+    ($name:path) => {
+        const _: () = {
+            thread_local! {
+                static CURRENT_CONTEXT_STACK: std::cell::RefCell<Vec<$crate::ContextValue<$name>>> =
+                    std::cell::RefCell::new(Vec::new());
+            }
+
+            $crate::context!($name, CURRENT_CONTEXT_VALUE);
+
+            impl $crate::StackedContext for $name {
+                fn stack() -> &'static std::thread::LocalKey<
+                    std::cell::RefCell<Vec<$crate::ContextValue<Self>>>,
+                > {
+                    &CURRENT_CONTEXT_STACK
+                }
+            }
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        task::{Context, Poll},
+    };
+
+    use super::StackedContext;
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Span(u32);
+
+    crate::context_stack!(Span);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Layer(u32);
+
+    crate::context_stack!(Layer);
+
+    #[test]
+    fn push_pop() {
+        let _g1 = Span(1).push();
+        let g2 = Span(2).push();
+
+        assert_eq!(Span::current_owned(), Some(Span(2)));
+        assert_eq!(Span::all(), vec![Span(1), Span(2)]);
+
+        drop(g2);
+        assert_eq!(Span::current_owned(), Some(Span(1)));
+        assert!(Span::with_current_ref(|x| x == Some(&Span(1))));
+    }
+
+    #[test]
+    fn typed_context_follows_top() {
+        assert_eq!(Span::current(), None);
+
+        let g1 = Span(1).push();
+        assert_eq!(Span::current(), Some(Span(1)));
+
+        let g2 = Span(2).push();
+        assert_eq!(Span::current(), Some(Span(2)));
+        assert_eq!(Span::nesting_depth(), 2);
+
+        let attached = Span(3).attach();
+        assert_eq!(Span::current(), Some(Span(3)));
+        assert_eq!(Span::all(), vec![Span(1), Span(2)]);
+        drop(attached);
+
+        drop(g2);
+        assert_eq!(Span::current(), Some(Span(1)));
+
+        drop(g1);
+        assert_eq!(Span::current(), None);
+        assert!(Span::all().is_empty());
+    }
+
+    #[test]
+    fn guards_dropped_out_of_order() {
+        let g1 = Span(1).push();
+        let _g2 = Span(2).push();
+
+        drop(g1);
+        assert_eq!(Span::all(), vec![Span(2)]);
+    }
+
+    #[test]
+    fn guard_returned_from_poll() {
+        let _g1 = Span(1).push();
+
+        let mut fut =
+            Box::pin(std::future::poll_fn(|_| Poll::Ready(Span(3).push())).with_pushed(Span(2)));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let Poll::Ready(guard) = fut.as_mut().poll(&mut cx) else {
+            panic!("future is not ready");
+        };
+
+        // Value of the guard is removed together with value of the wrapper.
+        assert_eq!(Span::all(), vec![Span(1)]);
+
+        drop(guard);
+        assert_eq!(Span::all(), vec![Span(1)]);
+    }
+
+    #[test]
+    fn debug() {
+        let fut = std::future::ready(()).with_pushed(Span(1));
+        assert_eq!(
+            format!("{fut:?}"),
+            "WithContextStack { inner: <Future>, value: Span(1) }"
+        );
+    }
+
+    #[test]
+    fn separate_stacks() {
+        let _span = Span(1).push();
+        let _layer = Layer(2).push();
+
+        assert_eq!(Span::all(), vec![Span(1)]);
+        assert_eq!(Layer::all(), vec![Layer(2)]);
+    }
+
+    #[tokio::test]
+    async fn future_push() {
+        let _g1 = Span(1).push();
+
+        let fut = async {
+            tokio::task::yield_now().await;
+            (Span::all(), Span::current())
+        }
+        .with_pushed(Span(2));
+
+        assert_eq!(fut.await, (vec![Span(1), Span(2)], Some(Span(2))));
+        assert_eq!(Span::all(), vec![Span(1)]);
+        assert_eq!(Span::current(), Some(Span(1)));
+    }
+
+    #[test]
+    fn future_push_multiple_polls() {
+        let _g1 = Span(1).push();
+
+        let mut polls = Vec::new();
+        let mut fut = Box::pin(
+            std::future::poll_fn(|cx| {
+                polls.push(Span::all());
+                if polls.len() > 1 {
+                    Poll::Ready(())
+                } else {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .with_pushed(Span(2)),
+        );
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(Span::all(), vec![Span(1)]);
+
+        let _g3 = Span(3).push();
+        assert!(fut.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(Span::all(), vec![Span(1), Span(3)]);
+
+        drop(fut);
+        assert_eq!(
+            polls,
+            [vec![Span(1), Span(2)], vec![Span(1), Span(3), Span(2)]]
+        );
+    }
+}
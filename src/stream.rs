@@ -0,0 +1,123 @@
+//! Context propagation for [`Stream`]s.
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::{stream::FusedStream, Stream};
+use pin_project_lite::pin_project;
+
+use crate::TypedContext;
+
+pin_project! {
+    /// Wrapper for a stream, responsible for managing its context.
+    #[derive(Clone, Debug)]
+    pub struct WithContextStream<S, T> {
+        #[pin]
+        inner: S,
+        value: Option<T>,
+    }
+}
+
+impl<S: Stream, T: TypedContext> Stream for WithContextStream<S, T> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        if let Some(ref x) = this.value {
+            let _guard = unsafe { x.attach_ref() };
+            this.inner.poll_next(cx)
+        } else {
+            this.inner.poll_next(cx)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: FusedStream, T: TypedContext> FusedStream for WithContextStream<S, T> {
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+/// Extension trait allowing to attach context to streams.
+pub trait StreamExt: Sized {
+    /// Set value as context for stream.
+    fn with<T>(self, value: T) -> WithContextStream<Self, T> {
+        WithContextStream {
+            inner: self,
+            value: Some(value),
+        }
+    }
+
+    /// Set optional value as context for stream.
+    ///
+    /// Primarily used with return value of [`TypedContext::current`].
+    fn with_opt<T>(self, value: Option<T>) -> WithContextStream<Self, T> {
+        WithContextStream { inner: self, value }
+    }
+
+    /// Take current context and set is as context for a stream.
+    ///
+    /// Basically it is `self.with_opt(T::current())`.
+    fn with_current<T: TypedContext>(self) -> WithContextStream<Self, T> {
+        self.with_opt(T::current())
+    }
+}
+
+impl<S: Sized + Stream> StreamExt for S {}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, StreamExt as _};
+
+    use super::StreamExt;
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tag(u32);
+
+    crate::context!(Tag);
+
+    #[tokio::test]
+    async fn multiple_items() {
+        let items = stream::iter(0..3)
+            .map(|_| Tag::current())
+            .with(Tag(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![Some(Tag(1)); 3]);
+        assert_eq!(Tag::current(), None);
+    }
+
+    #[tokio::test]
+    async fn pending_between_items() {
+        let items = stream::iter(0..3)
+            .then(|_| async {
+                tokio::task::yield_now().await;
+                Tag::current()
+            })
+            .with(Tag(2))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![Some(Tag(2)); 3]);
+    }
+
+    #[tokio::test]
+    async fn completes_with_error() {
+        let items = stream::iter(vec![Ok(()), Err(())])
+            .map(|x| x.map(|_| Tag::current()).map_err(|_| Tag::current()))
+            .with(Tag(3))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![Ok(Some(Tag(3))), Err(Some(Tag(3)))]);
+    }
+}
@@ -0,0 +1,187 @@
+//! Ad-hoc contexts, identified by a tag instead of a dedicated type.
+
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{ContextGuard, ContextSlot, TlsBackend, TypedContext};
+
+thread_local! {
+    static TAGGED_SLOTS: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Storage of [`TagContext`] and [`SharedContext`](crate::SharedContext) values, keeping separate
+/// slot for each context type.
+///
+/// Can be used as [`TypedContext::Backend`] of generic context types, since it doesn't require
+/// separate TLS variable for each type.
+#[derive(Debug)]
+pub struct TagBackend(());
+
+static TAG_BACKEND: TagBackend = TagBackend(());
+
+impl TagBackend {
+    /// Get the storage.
+    pub fn get() -> &'static Self {
+        &TAG_BACKEND
+    }
+}
+
+impl<T: 'static> TlsBackend<T> for TagBackend {
+    fn try_with<R>(&'static self, f: impl FnOnce(&RefCell<ContextSlot<T>>) -> R) -> Option<R> {
+        let slot = TAGGED_SLOTS
+            .try_with(|slots| {
+                let mut slots = slots.borrow_mut();
+                let slot = slots
+                    .entry(TypeId::of::<T>())
+                    .or_insert_with(|| Box::new(RefCell::new(ContextSlot::<T>::new())));
+                slot.downcast_ref::<RefCell<ContextSlot<T>>>()
+                    .map(|x| x as *const RefCell<ContextSlot<T>>)
+            })
+            .ok()
+            .flatten()?;
+
+        // SAFETY: slots are boxed and never removed from the map until the thread is destroyed, so
+        // pointer stays valid even if other slots are inserted by `f`. Map itself is not borrowed
+        // while `f` is running.
+        Some(f(unsafe { &*slot }))
+    }
+}
+
+/// Compute tag for [`TagContext`] from its name.
+///
+/// Tag is 64-bit FNV-1a hash of the name, so it can be computed in const context.
+pub const fn tag(name: &str) -> u64 {
+    let bytes = name.as_bytes();
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        i += 1;
+    }
+
+    hash
+}
+
+/// Context for values of arbitrary type, identified by a tag.
+///
+/// Allows to use values like `u64` or `String` as a context without defining newtype with
+/// [`context!`](crate::context). Contexts with different tags (or different value types) are
+/// independent. Tag is usually computed from its name with [`tag`].
+///
+/// With `metrics` feature counters of all tag contexts are shared.
+///
+/// Requires `std` feature.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{tag, TagContext, TypedContext};
+///
+/// type RequestId = TagContext<{ tag("request_id") }, u64>;
+/// type UserId = TagContext<{ tag("user_id") }, u64>;
+///
+/// let _guard = RequestId::set(42);
+///
+/// assert_eq!(RequestId::current().map(|x| *x), Some(42));
+/// assert_eq!(UserId::current(), None);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TagContext<const TAG: u64, T>(pub T);
+
+impl<const TAG: u64, T: Clone + 'static> TagContext<TAG, T> {
+    /// Set value as current context.
+    ///
+    /// Shortcut for `TagContext(value).attach()`.
+    pub fn set(value: T) -> ContextGuard<Self> {
+        Self(value).attach()
+    }
+
+    /// Get clone of the current value.
+    pub fn get() -> Option<T> {
+        Self::current_map(|x| x.0.clone())
+    }
+
+    /// Get inner value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<const TAG: u64, T> Deref for TagContext<TAG, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<const TAG: u64, T> DerefMut for TagContext<TAG, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<const TAG: u64, T> From<T> for TagContext<TAG, T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<const TAG: u64, T: Clone + 'static> TypedContext for TagContext<TAG, T> {
+    type Backend = TagBackend;
+
+    fn tls() -> &'static Self::Backend {
+        TagBackend::get()
+    }
+
+    crate::__context_counters!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tag, TagContext};
+    use crate::{FutureExt, TypedContext};
+
+    type RequestId = TagContext<{ tag("request_id") }, u64>;
+    type UserId = TagContext<{ tag("user_id") }, u64>;
+    type RequestName = TagContext<{ tag("request_id") }, String>;
+
+    #[test]
+    fn independent_tags() {
+        let _request_guard = RequestId::set(1);
+        let user_guard = UserId::set(2);
+        let _name_guard = RequestName::set("name".into());
+
+        assert_eq!(RequestId::get(), Some(1));
+        assert_eq!(UserId::get(), Some(2));
+        assert_eq!(RequestName::get().as_deref(), Some("name"));
+
+        drop(user_guard);
+        assert_eq!(UserId::get(), None);
+        assert_eq!(RequestId::get(), Some(1));
+    }
+
+    #[test]
+    fn nested() {
+        let _outer = RequestId::set(1);
+        RequestId::from(2).scoped(|| {
+            let _inner = UserId::set(3);
+            assert_eq!((RequestId::get(), UserId::get()), (Some(2), Some(3)));
+        });
+        assert_eq!((RequestId::get(), UserId::get()), (Some(1), None));
+    }
+
+    #[tokio::test]
+    async fn propagation() {
+        let res = tokio::spawn(async { RequestId::get() }.with(RequestId::from(4)))
+            .await
+            .unwrap();
+        assert_eq!(res, Some(4));
+    }
+}
@@ -0,0 +1,125 @@
+//! Context propagation into spawned threads.
+
+use std::{
+    any::Any,
+    thread::{self, JoinHandle},
+};
+
+use crate::TypedContext;
+
+/// Captured value of some context, which can be attached in other thread.
+trait CapturedContext: Send {
+    /// Attach captured value, returning type-erased guard.
+    fn attach(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: TypedContext + Send> CapturedContext for Option<T> {
+    fn attach(self: Box<Self>) -> Box<dyn Any> {
+        Box::new(self.map(T::attach))
+    }
+}
+
+/// Builder, capturing current values of several contexts and attaching them in spawned thread.
+///
+/// # Example:
+///
+/// ```
+/// use tyco::{context, ThreadContextPropagator, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct RequestId(u64);
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct UserId(u64);
+///
+/// context!(RequestId, REQUEST_ID_CONTEXT);
+/// context!(UserId, USER_ID_CONTEXT);
+///
+/// let _request_guard = RequestId(1).attach();
+/// let _user_guard = UserId(2).attach();
+///
+/// let handle = ThreadContextPropagator::new()
+///     .capture::<RequestId>()
+///     .capture::<UserId>()
+///     .spawn(|| (RequestId::current(), UserId::current()));
+///
+/// assert_eq!(handle.join().unwrap(), (Some(RequestId(1)), Some(UserId(2))));
+/// ```
+#[derive(Default)]
+pub struct ThreadContextPropagator {
+    captured: Vec<Box<dyn CapturedContext>>,
+}
+
+impl ThreadContextPropagator {
+    /// Create builder without any captured contexts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capture current value of the context.
+    pub fn capture<T: TypedContext + Send>(mut self) -> Self {
+        self.captured.push(Box::new(T::current()));
+        self
+    }
+
+    /// Spawn new thread with captured contexts attached.
+    pub fn spawn<F, R>(self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        thread::spawn(self.bind(f))
+    }
+
+    /// Wrap function, so captured contexts are attached while it is called.
+    pub(crate) fn bind<F, R>(self, f: F) -> impl FnOnce() -> R + Send + 'static
+    where
+        F: FnOnce() -> R + Send + 'static,
+    {
+        move || {
+            let _guards = self
+                .captured
+                .into_iter()
+                .map(CapturedContext::attach)
+                .collect::<Vec<_>>();
+            f()
+        }
+    }
+}
+
+/// Spawn new thread with current value of the context attached.
+///
+/// Basically it is `ThreadContextPropagator::new().capture::<T>().spawn(f)`.
+pub fn spawn_with_context<T, F, R>(f: F) -> JoinHandle<R>
+where
+    T: TypedContext + Send,
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    ThreadContextPropagator::new().capture::<T>().spawn(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spawn_with_context;
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct RequestId(u64);
+
+    crate::context!(RequestId);
+
+    #[test]
+    fn spawn() {
+        let _guard = RequestId(1).attach();
+
+        let handle = spawn_with_context::<RequestId, _, _>(RequestId::current);
+        assert_eq!(handle.join().unwrap(), Some(RequestId(1)));
+    }
+
+    #[test]
+    fn spawn_without_value() {
+        let handle = spawn_with_context::<RequestId, _, _>(RequestId::current);
+        assert_eq!(handle.join().unwrap(), None);
+    }
+}
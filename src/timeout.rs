@@ -0,0 +1,148 @@
+//! Guards, restoring previous value of the context after timeout.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::{Instant, Sleep};
+
+use crate::{ContextGuard, TypedContext};
+
+/// Guard, created with [`ContextGuard::timeout`], which restores previous value of the context after
+/// timeout.
+///
+/// Guard is a future, which completes when timeout expires, dropping inner guard. Since guard must be
+/// dropped on the thread, where it was created, there is no background task: timeout is enforced
+/// only when guard is polled (usually in `tokio::select!` alongside with the work future) or dropped.
+///
+/// Requires `tokio` feature.
+///
+/// # Example:
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tyco::{context, TypedContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TraceId(u64);
+///
+/// context!(TraceId);
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let mut guard = TraceId(1).attach().timeout(Duration::from_millis(10));
+///
+/// tokio::select! {
+///     _ = &mut guard => {}
+///     _ = std::future::pending::<()>() => unreachable!(),
+/// }
+///
+/// assert!(guard.is_expired());
+/// assert_eq!(TraceId::current(), None);
+/// # });
+/// ```
+#[must_use = "if unused the timeout is never awaited and previous value is restored immediately"]
+pub struct TimeoutGuard<T: TypedContext> {
+    guard: Option<ContextGuard<T>>,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<T: TypedContext> TimeoutGuard<T> {
+    pub(crate) fn new(guard: ContextGuard<T>, duration: Duration) -> Self {
+        Self {
+            guard: Some(guard),
+            sleep: Box::pin(tokio::time::sleep(duration)),
+        }
+    }
+
+    /// Get instant, when timeout expires.
+    pub fn deadline(&self) -> Instant {
+        self.sleep.deadline()
+    }
+
+    /// Check whether timeout expired and previous value was restored.
+    pub fn is_expired(&self) -> bool {
+        self.guard.is_none()
+    }
+
+    /// Cancel timeout and get inner guard.
+    ///
+    /// Returns `None` if timeout already expired.
+    pub fn into_inner(mut self) -> Option<ContextGuard<T>> {
+        self.guard.take()
+    }
+}
+
+impl<T: TypedContext> Future for TimeoutGuard<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.guard.is_some() {
+            ready!(self.sleep.as_mut().poll(cx));
+            self.guard = None;
+        }
+
+        Poll::Ready(())
+    }
+}
+
+impl<T: TypedContext + fmt::Debug> fmt::Debug for TimeoutGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutGuard")
+            .field("value", &self.guard.as_ref().map(ContextGuard::value))
+            .field("deadline", &self.deadline())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::TypedContext;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TraceId(u64);
+
+    crate::context!(TraceId);
+
+    #[tokio::test]
+    async fn cleared_after_timeout() {
+        let _outer = TraceId(1).attach();
+        let mut guard = TraceId(2).attach().timeout(Duration::from_millis(50));
+
+        let work = async {
+            tokio::task::yield_now().await;
+            TraceId::current()
+        };
+        tokio::select! {
+            _ = &mut guard => unreachable!(),
+            current = work => assert_eq!(current, Some(TraceId(2))),
+        }
+        assert!(!guard.is_expired());
+
+        let work = tokio::time::sleep(Duration::from_secs(10));
+        tokio::select! {
+            _ = &mut guard => {}
+            _ = work => unreachable!(),
+        }
+
+        assert!(guard.is_expired());
+        assert_eq!(TraceId::current(), Some(TraceId(1)));
+        assert!(guard.into_inner().is_none());
+    }
+
+    #[tokio::test]
+    async fn into_inner() {
+        let guard = TraceId(1).attach().timeout(Duration::from_secs(1));
+        let guard = guard.into_inner().unwrap();
+
+        assert_eq!(TraceId::current(), Some(TraceId(1)));
+        drop(guard);
+        assert_eq!(TraceId::current(), None);
+    }
+}
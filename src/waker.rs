@@ -0,0 +1,196 @@
+//! Wakers, which set context while waking the task.
+
+use alloc::{sync::Arc, task::Wake};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use pin_project_lite::pin_project;
+
+use crate::TypedContext;
+
+/// Wrapper for a [`Waker`], setting context while task is woken.
+///
+/// Regular wrappers (like [`WithContext`](crate::WithContext)) set context only while future is
+/// polled. If future stores waker and it is woken later (for example, from I/O driver thread), code,
+/// executed by [`Waker::wake`] (which belongs to executor), doesn't see the context. This wrapper
+/// sets context for the duration of the wake call, which can be useful with custom executors or
+/// wakers, which log or trace wake-ups.
+///
+/// # Example:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use std::{sync::Arc, task::Waker};
+///
+/// use tyco::{context, ContextAwareWaker};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct TaskName(&'static str);
+///
+/// context!(TaskName);
+///
+/// let waker = ContextAwareWaker::new(Waker::noop().clone(), Arc::new(TaskName("worker")));
+/// waker.into_waker().wake();
+/// # }
+/// ```
+pub struct ContextAwareWaker<T> {
+    inner: Waker,
+    value: Arc<T>,
+}
+
+impl<T: TypedContext + Send + Sync> ContextAwareWaker<T> {
+    /// Wrap waker, setting value as context while it is woken.
+    pub fn new(inner: Waker, value: Arc<T>) -> Self {
+        Self { inner, value }
+    }
+
+    /// Convert into [`Waker`].
+    pub fn into_waker(self) -> Waker {
+        Waker::from(Arc::new(self))
+    }
+}
+
+impl<T: TypedContext + Send + Sync> Wake for ContextAwareWaker<T> {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let _guard = unsafe { self.value.attach_ref() };
+        self.inner.wake_by_ref()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ContextAwareWaker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContextAwareWaker")
+            .field("inner", &self.inner)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+pin_project! {
+    /// Wrapper for a future, setting context while it is polled and while it's waker is woken.
+    ///
+    /// Created with [`FutureExt::with_context_waker`](crate::FutureExt::with_context_waker).
+    pub struct WithContextWaker<F, T> {
+        #[pin]
+        inner: F,
+        value: Arc<T>,
+        waker: Option<(Waker, Waker)>,
+    }
+}
+
+impl<F, T> WithContextWaker<F, T> {
+    pub(crate) fn new(inner: F, value: T) -> Self {
+        Self {
+            inner,
+            value: Arc::new(value),
+            waker: None,
+        }
+    }
+}
+
+impl<F: Future, T: TypedContext + Send + Sync> Future for WithContextWaker<F, T> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        // Wrapped waker is reused while executor passes the same waker.
+        let waker = match this.waker {
+            Some((original, wrapped)) if original.will_wake(cx.waker()) => wrapped,
+            waker => {
+                let wrapped =
+                    ContextAwareWaker::new(cx.waker().clone(), this.value.clone()).into_waker();
+                &waker.insert((cx.waker().clone(), wrapped)).1
+            }
+        };
+
+        let _guard = unsafe { this.value.attach_ref() };
+        this.inner.poll(&mut Context::from_waker(waker))
+    }
+}
+
+impl<F, T: fmt::Debug> fmt::Debug for WithContextWaker<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithContextWaker")
+            .field("inner", &format_args!("<Future>"))
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::{
+        future::Future,
+        sync::{Arc, Mutex},
+        task::{Poll, Wake, Waker},
+    };
+
+    use crate::{FutureExt, TypedContext};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TaskName(&'static str);
+
+    crate::context!(TaskName);
+
+    #[derive(Default)]
+    struct RecordingWaker(Mutex<Vec<Option<TaskName>>>);
+
+    impl Wake for RecordingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref()
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.lock().unwrap().push(TaskName::current());
+        }
+    }
+
+    #[test]
+    fn wake_from_other_thread() {
+        let recorder = Arc::new(RecordingWaker::default());
+        let waker = Waker::from(recorder.clone());
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let mut polled = false;
+        let mut fut = std::pin::pin!(std::future::poll_fn(|cx| {
+            if std::mem::replace(&mut polled, true) {
+                return Poll::Ready(TaskName::current());
+            }
+            let waker = cx.waker().clone();
+            std::thread::spawn(move || waker.wake()).join().unwrap();
+            Poll::Pending
+        })
+        .with_context_waker(TaskName("worker")));
+
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(
+            fut.as_mut().poll(&mut cx),
+            Poll::Ready(Some(TaskName("worker")))
+        );
+        assert_eq!(*recorder.0.lock().unwrap(), vec![Some(TaskName("worker"))]);
+    }
+
+    #[tokio::test]
+    async fn with_runtime() {
+        let res = tokio::spawn(
+            async {
+                tokio::task::yield_now().await;
+                TaskName::current()
+            }
+            .with_context_waker(TaskName("task")),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res, Some(TaskName("task")));
+    }
+}
@@ -0,0 +1,147 @@
+#![cfg(feature = "derive")]
+
+use tyco::{FutureExt, TypedContext};
+
+#[derive(Clone, Debug, PartialEq, TypedContext)]
+struct TraceId(String);
+
+#[derive(Clone, Debug, PartialEq, TypedContext)]
+struct HTTPRequestId(u64);
+
+#[test]
+fn several_contexts_in_module() {
+    let _trace_guard = TraceId("trace".into()).attach();
+    let request_guard = HTTPRequestId(1).attach();
+
+    assert_eq!(TraceId::current(), Some(TraceId("trace".into())));
+    assert_eq!(HTTPRequestId::current(), Some(HTTPRequestId(1)));
+
+    drop(request_guard);
+
+    assert_eq!(TraceId::current(), Some(TraceId("trace".into())));
+    assert_eq!(HTTPRequestId::current(), None);
+}
+
+#[test]
+fn deterministic_tls_name() {
+    __TYCO_CONTEXT_HTTP_REQUEST_ID.with(|v| assert!(v.borrow().is_none()));
+    let _guard = HTTPRequestId(2).attach();
+    __TYCO_CONTEXT_HTTP_REQUEST_ID.with(|v| assert!(v.borrow().is_some()));
+}
+
+#[tokio::test]
+async fn propagate_to_future() {
+    let res = tokio::spawn(async { TraceId::current() }.with(TraceId("spawned".into())))
+        .await
+        .unwrap();
+
+    assert_eq!(res, Some(TraceId("spawned".into())));
+}
+
+#[tyco::with_context(TraceId, HTTPRequestId)]
+async fn current_in_attributed_fn() -> (Option<TraceId>, Option<HTTPRequestId>) {
+    tokio::task::yield_now().await;
+    (TraceId::current(), HTTPRequestId::current())
+}
+
+#[tokio::test]
+async fn with_context_async() {
+    let trace_guard = TraceId("async".into()).attach();
+    let request_guard = HTTPRequestId(3).attach();
+
+    let fut = current_in_attributed_fn();
+    let res = {
+        let mut fut = std::pin::pin!(fut);
+        // first poll captures context
+        assert!(futures::poll!(fut.as_mut()).is_pending());
+        drop((trace_guard, request_guard));
+        fut.await
+    };
+
+    assert_eq!(res, (Some(TraceId("async".into())), Some(HTTPRequestId(3))));
+}
+
+#[tyco::with_context(HTTPRequestId)]
+fn replace_in_attributed_fn(
+    value: u64,
+) -> Result<Option<HTTPRequestId>, std::num::TryFromIntError> {
+    HTTPRequestId::replace(HTTPRequestId(u32::try_from(value)?.into()));
+    Ok(HTTPRequestId::current())
+}
+
+#[test]
+fn with_context_sync() {
+    let _guard = HTTPRequestId(4).attach();
+
+    assert_eq!(replace_in_attributed_fn(5), Ok(Some(HTTPRequestId(5))));
+    assert!(replace_in_attributed_fn(u64::MAX).is_err());
+    assert_eq!(HTTPRequestId::current(), Some(HTTPRequestId(4)));
+}
+
+#[tyco::with_context(HTTPRequestId)]
+async fn parse_in_attributed_fn(value: &str) -> Result<u64, std::num::ParseIntError> {
+    let value = value.parse::<u64>()?;
+    Ok(value + HTTPRequestId::current().map_or(0, |x| x.0))
+}
+
+#[tokio::test]
+async fn with_context_async_result() {
+    let _guard = HTTPRequestId(1).attach();
+
+    assert_eq!(parse_in_attributed_fn("1").await, Ok(2));
+    assert!(parse_in_attributed_fn("x").await.is_err());
+}
+
+#[tyco::context_fn(push = TraceId, clear = HTTPRequestId)]
+fn context_fn_body(fail: bool) -> Result<Option<HTTPRequestId>, &'static str> {
+    TraceId::replace(TraceId("inner".into()));
+    if fail {
+        Err("failed")?;
+    }
+    Ok(HTTPRequestId::current())
+}
+
+#[test]
+fn context_fn() {
+    let _trace_guard = TraceId("outer".into()).attach();
+    let _request_guard = HTTPRequestId(1).attach();
+
+    assert_eq!(context_fn_body(false), Ok(None));
+    assert_eq!(context_fn_body(true), Err("failed"));
+
+    let res = std::panic::catch_unwind(|| {
+        #[tyco::context_fn(clear = TraceId)]
+        fn panicking() {
+            panic!("oops")
+        }
+        panicking()
+    });
+    assert!(res.is_err());
+
+    assert_eq!(TraceId::current(), Some(TraceId("outer".into())));
+    assert_eq!(HTTPRequestId::current(), Some(HTTPRequestId(1)));
+}
+
+#[tyco::typed_context(default = Region::Global)]
+#[derive(Clone, Debug, PartialEq)]
+enum Region {
+    Global,
+    Local(&'static str),
+}
+
+#[tyco::typed_context]
+#[derive(Clone, Debug, PartialEq)]
+struct SessionId(u64);
+
+#[test]
+fn typed_context_attribute() {
+    assert_eq!(Region::current(), Some(Region::Global));
+    assert!(!Region::is_set());
+    assert_eq!(SessionId::current(), None);
+
+    let _region_guard = Region::Local("eu").attach();
+    let _session_guard = SessionId(1).attach();
+
+    assert_eq!(Region::current(), Some(Region::Local("eu")));
+    assert_eq!(SessionId::current(), Some(SessionId(1)));
+}
@@ -0,0 +1,61 @@
+//! Context propagation doesn't depend on tokio, check it with other runtimes.
+
+#![cfg(feature = "std")]
+
+use tyco::{context, FutureExt, TypedContext};
+
+#[derive(Clone, Debug, PartialEq)]
+struct RequestId(u64);
+
+context!(RequestId);
+
+async fn current_after_yield() -> Option<RequestId> {
+    futures::future::ready(()).await;
+    async_std::task::yield_now().await;
+    RequestId::current()
+}
+
+#[async_std::test]
+async fn async_std_with() {
+    let res = async_std::task::spawn(current_after_yield().with(RequestId(1))).await;
+    assert_eq!(res, Some(RequestId(1)));
+}
+
+#[async_std::test]
+async fn async_std_with_opt() {
+    let res = async_std::task::spawn(current_after_yield().with_opt(Some(RequestId(2)))).await;
+    assert_eq!(res, Some(RequestId(2)));
+
+    let res = async_std::task::spawn(current_after_yield().with_opt::<RequestId>(None)).await;
+    assert_eq!(res, None);
+}
+
+#[async_std::test]
+async fn async_std_with_current() {
+    let guard = RequestId(3).attach();
+    let fut = current_after_yield().with_current::<RequestId>();
+    drop(guard);
+
+    assert_eq!(async_std::task::spawn(fut).await, Some(RequestId(3)));
+    assert_eq!(RequestId::current(), None);
+}
+
+#[test]
+fn smol_spawn() {
+    let ex = smol::Executor::new();
+
+    let res = smol::block_on(ex.run(async {
+        let guard = RequestId(4).attach();
+        let task = ex.spawn(
+            async {
+                smol::future::yield_now().await;
+                RequestId::current()
+            }
+            .with_current::<RequestId>(),
+        );
+        drop(guard);
+        task.await
+    }));
+
+    assert_eq!(res, Some(RequestId(4)));
+}
@@ -0,0 +1,63 @@
+//! Contexts, defined in private modules and re-exported, or with restricted constructors.
+
+#![cfg(feature = "std")]
+
+mod inner {
+    #[derive(Clone, Debug, PartialEq)]
+    #[non_exhaustive]
+    pub struct TenantId {
+        pub id: u64,
+    }
+
+    impl TenantId {
+        pub fn new(id: u64) -> Self {
+            Self { id }
+        }
+    }
+
+    tyco::context!(TenantId);
+
+    pub mod nested {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Region(String);
+
+        impl Region {
+            pub fn new(name: &str) -> Self {
+                Self(name.into())
+            }
+        }
+
+        tyco::context!(Region, REGION_CONTEXT, default = Region::new("default"));
+    }
+}
+
+pub use inner::{nested::Region, TenantId};
+use tyco::{FutureExt, TypedContext};
+
+static STATIC_TENANT: std::sync::OnceLock<TenantId> = std::sync::OnceLock::new();
+
+#[test]
+fn reexported_context() {
+    let _guard = TenantId::new(1).attach();
+
+    assert_eq!(TenantId::current(), Some(TenantId::new(1)));
+    assert_eq!(Region::current(), Some(Region::new("default")));
+}
+
+#[test]
+fn non_exhaustive_attach_ref() {
+    let tenant = STATIC_TENANT.get_or_init(|| TenantId::new(2));
+    let _guard = unsafe { tenant.attach_ref() };
+
+    assert_eq!(TenantId::current_map(|x| x.id), Some(2));
+}
+
+#[tokio::test]
+async fn reexported_future() {
+    let res = async { (TenantId::current(), Region::current()) }
+        .with(TenantId::new(3))
+        .with(Region::new("eu"))
+        .await;
+
+    assert_eq!(res, (Some(TenantId::new(3)), Some(Region::new("eu"))));
+}
@@ -0,0 +1,296 @@
+//! Derive and attribute macros for [`tyco`](https://docs.rs/tyco).
+//!
+//! This crate is not intended to be used directly, enable `derive` feature of `tyco` instead.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote, quote_spanned};
+use syn::{
+    parse::Parser, parse_macro_input, punctuated::Punctuated, spanned::Spanned, DeriveInput, Error,
+    Expr, Ident, ItemFn, Path, Token,
+};
+
+/// Derive `TypedContext` for a type.
+///
+/// See documentation of `tyco::TypedContext` derive macro for details.
+#[proc_macro_derive(TypedContext)]
+pub fn derive_typed_context(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand_typed_context(&input, None)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implement `TypedContext` for a type, optionally with default value.
+///
+/// See documentation of `tyco::typed_context` attribute macro for details.
+#[proc_macro_attribute]
+pub fn typed_context(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand_typed_context_attr(attr.into(), item.into())
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Propagate current values of contexts into function body.
+///
+/// See documentation of `tyco::with_context` attribute macro for details.
+#[proc_macro_attribute]
+pub fn with_context(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand_with_context(attr.into(), item.into())
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Attach or clear contexts for the duration of sync function call.
+///
+/// See documentation of `tyco::context_fn` attribute macro for details.
+#[proc_macro_attribute]
+pub fn context_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand_context_fn(attr.into(), item.into())
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_with_context(
+    attr: proc_macro2::TokenStream,
+    item: proc_macro2::TokenStream,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let contexts = Punctuated::<Path, Token![,]>::parse_terminated.parse2(attr)?;
+    if contexts.is_empty() {
+        return Err(Error::new(
+            Span::call_site(),
+            "expected at least one context type, like `#[with_context(TraceId)]`",
+        ));
+    }
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = parse_fn(item, "with_context")?;
+
+    let context_asserts = contexts.iter().map(assert_typed_context);
+
+    let body = if sig.asyncness.is_some() {
+        let wrappers = contexts.iter().map(|ty| {
+            quote! { let __tyco_fut = ::tyco::FutureExt::with_current::<#ty>(__tyco_fut); }
+        });
+        quote! {
+            let __tyco_fut = async move #block;
+            #(#wrappers)*
+            __tyco_fut.await
+        }
+    } else {
+        let guards = contexts.iter().map(|ty| {
+            quote! { <#ty as ::tyco::TypedContext>::current().map(::tyco::TypedContext::attach) }
+        });
+        let stmts = &block.stmts;
+        quote! {
+            let __tyco_guards = (#(#guards,)*);
+            #(#stmts)*
+        }
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            #(#context_asserts)*
+            #body
+        }
+    })
+}
+
+/// Argument of `context_fn`: `push = Type` or `clear = Type`.
+struct ContextFnArg {
+    clear: bool,
+    ty: Path,
+}
+
+impl syn::parse::Parse for ContextFnArg {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let kind = input.parse::<Ident>()?;
+        let clear = match kind.to_string().as_str() {
+            "push" => false,
+            "clear" => true,
+            _ => return Err(Error::new(kind.span(), "expected `push` or `clear`")),
+        };
+        input.parse::<Token![=]>()?;
+
+        Ok(Self {
+            clear,
+            ty: input.parse()?,
+        })
+    }
+}
+
+fn expand_context_fn(
+    attr: proc_macro2::TokenStream,
+    item: proc_macro2::TokenStream,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let args = Punctuated::<ContextFnArg, Token![,]>::parse_terminated.parse2(attr)?;
+    if args.is_empty() {
+        return Err(Error::new(
+            Span::call_site(),
+            "expected at least one context, like `#[context_fn(push = TraceId)]`",
+        ));
+    }
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = parse_fn(item, "context_fn")?;
+    if let Some(asyncness) = sig.asyncness {
+        return Err(Error::new(
+            asyncness.span(),
+            "`context_fn` can't be applied to `async fn`, use `tyco::with_context` or `tyco::FutureExt` instead",
+        ));
+    }
+
+    let context_asserts = args.iter().map(|arg| assert_typed_context(&arg.ty));
+    let guards = args.iter().map(|ContextFnArg { clear, ty }| {
+        if *clear {
+            quote! { ::tyco::__clear_context::<#ty>() }
+        } else {
+            quote! { <#ty as ::tyco::TypedContext>::current().map(::tyco::TypedContext::attach) }
+        }
+    });
+    let stmts = &block.stmts;
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            #(#context_asserts)*
+            let __tyco_guards = (#(#guards,)*);
+            #(#stmts)*
+        }
+    })
+}
+
+fn parse_fn(item: proc_macro2::TokenStream, macro_name: &str) -> syn::Result<ItemFn> {
+    syn::parse2::<ItemFn>(item).map_err(|err| {
+        Error::new(
+            err.span(),
+            format!("`{macro_name}` can only be applied to functions"),
+        )
+    })
+}
+
+/// Check, that type implements `TypedContext`, reporting error at the type.
+fn assert_typed_context(ty: &Path) -> proc_macro2::TokenStream {
+    quote_spanned! {ty.span()=>
+        const _: fn() = || {
+            fn assert_typed_context<T: ::tyco::TypedContext>() {}
+            assert_typed_context::<#ty>();
+        };
+    }
+}
+
+/// Argument of `typed_context`: `default = expr`.
+struct TypedContextArgs {
+    default: Option<Expr>,
+}
+
+impl syn::parse::Parse for TypedContextArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        if input.is_empty() {
+            return Ok(Self { default: None });
+        }
+
+        let key = input.parse::<Ident>()?;
+        if key != "default" {
+            return Err(Error::new(key.span(), "expected `default = <expr>`"));
+        }
+        input.parse::<Token![=]>()?;
+        let default = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+
+        Ok(Self {
+            default: Some(default),
+        })
+    }
+}
+
+fn expand_typed_context_attr(
+    attr: proc_macro2::TokenStream,
+    item: proc_macro2::TokenStream,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let args = syn::parse2::<TypedContextArgs>(attr)?;
+    let input = syn::parse2::<DeriveInput>(item.clone()).map_err(|err| {
+        Error::new(
+            err.span(),
+            "`typed_context` can only be applied to structs and enums",
+        )
+    })?;
+    let expanded = expand_typed_context(&input, args.default.as_ref())?;
+
+    Ok(quote! {
+        #item
+        #expanded
+    })
+}
+
+fn expand_typed_context(
+    input: &DeriveInput,
+    default: Option<&Expr>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "`TypedContext` cannot be derived for generic types, because thread-local storage \
+             cannot be generic; use `tyco::context!` on a concrete type instead",
+        ));
+    }
+
+    let name = &input.ident;
+    let tls_name = tls_name(name);
+    let clone_assert = quote_spanned! {name.span()=>
+        const _: fn() = || {
+            fn assert_clone<T: ::tyco::__ContextClone>() {}
+            assert_clone::<#name>();
+        };
+    };
+    let init = match default {
+        Some(default) => quote! { ::tyco::ContextSlot::with_default(#default) },
+        None => quote! { ::tyco::ContextSlot::new() },
+    };
+
+    Ok(quote! {
+        #clone_assert
+
+        ::std::thread_local! {
+            static #tls_name: ::std::cell::RefCell<::tyco::ContextSlot<#name>> =
+                ::std::cell::RefCell::new(#init);
+        }
+
+        impl ::tyco::TypedContext for #name {
+            type Backend = ::std::thread::LocalKey<
+                ::std::cell::RefCell<::tyco::ContextSlot<Self>>,
+            >;
+
+            fn tls() -> &'static Self::Backend {
+                &#tls_name
+            }
+
+            ::tyco::__context_counters!();
+        }
+    })
+}
+
+/// Name of TLS variable: `__TYCO_CONTEXT_` followed by name of type in `SCREAMING_SNAKE_CASE`.
+fn tls_name(name: &Ident) -> Ident {
+    let chars = name.to_string().chars().collect::<Vec<_>>();
+    let mut snake = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        let word_start = i != 0
+            && c.is_uppercase()
+            && (!chars[i - 1].is_uppercase() || chars.get(i + 1).is_some_and(|x| x.is_lowercase()));
+        if word_start && chars[i - 1] != '_' {
+            snake.push('_');
+        }
+        snake.extend(c.to_uppercase());
+    }
+
+    format_ident!("__TYCO_CONTEXT_{}", snake, span = Span::call_site())
+}